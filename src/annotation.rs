@@ -0,0 +1,378 @@
+//! Annotation files, like `s1-segments.csv`, which name and size segments of a
+//! tape. They can be loaded, edited and saved without losing `#` comments,
+//! blank lines grouping the rows, `auto` lengths, the formatting of rows
+//! which were not changed, or the line endings.
+
+#![warn(missing_docs)]
+
+use std::{fs, io::Write, path::Path};
+
+use anyhow::{Result, bail};
+use csv::StringRecord;
+
+use crate::segment::{SegmentHeader, SegmentLen};
+
+/// An annotation file, which is a CSV of segment headers with comments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnotationFile {
+    /// The column names.
+    columns: StringRecord,
+    lines: Vec<Line>,
+    /// Whether lines end with CRLF instead of LF.
+    crlf: bool,
+    /// Whether the last line ends with a line ending.
+    final_newline: bool,
+}
+
+/// A line of an annotation file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Line {
+    /// A blank line or a comment starting with `#`, kept verbatim.
+    Text(String),
+    /// The header row of column names, kept verbatim.
+    Columns(String),
+    /// A row of the CSV.
+    Row(Box<Row>),
+}
+
+/// A row of the CSV.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Row {
+    header: SegmentHeader,
+    /// The row as it was read and the header parsed from it, for writing it
+    /// verbatim when it has not changed.
+    original: Option<(String, SegmentHeader)>,
+}
+
+impl AnnotationFile {
+    /// Creates an empty annotation file with the standard columns.
+    pub fn new() -> Self {
+        let columns = "Path,Offset,Length";
+        AnnotationFile {
+            columns: StringRecord::from(columns.split(',').collect::<Vec<_>>()),
+            lines: vec![Line::Columns(columns.to_owned())],
+            crlf: false,
+            final_newline: true,
+        }
+    }
+
+    /// Reads an annotation file.
+    pub fn open(path: &Path) -> Result<Self> {
+        AnnotationFile::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses an annotation file. Rows must each be on one line. Lines may end
+    /// with LF or CRLF, as given by the first line.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut columns = None;
+        let mut lines = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                lines.push(Line::Text(line.to_owned()));
+                continue;
+            }
+            let record = parse_record(line)?;
+            let Some(columns) = &columns else {
+                columns = Some(record);
+                lines.push(Line::Columns(line.to_owned()));
+                continue;
+            };
+            let header: SegmentHeader = match record.deserialize(Some(columns)) {
+                Ok(header) => header,
+                Err(err) => bail!("line {}: {err}", i + 1),
+            };
+            lines.push(Line::Row(Box::new(Row {
+                original: Some((line.to_owned(), header.clone())),
+                header,
+            })));
+        }
+        let Some(columns) = columns else {
+            bail!("annotation file has no header row");
+        };
+        Ok(AnnotationFile {
+            columns,
+            lines,
+            crlf: text.find('\n').is_some_and(|i| text[..i].ends_with('\r')),
+            final_newline: text.ends_with('\n'),
+        })
+    }
+
+    /// Writes the annotation file to a path.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        fs::write(path, buf)?;
+        Ok(())
+    }
+
+    /// Writes the annotation file. Unchanged rows are written as they were
+    /// read and changed rows are written in the columns of the file.
+    pub fn write<W: Write>(&self, mut w: W) -> Result<()> {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Text(text) | Line::Columns(text) => w.write_all(text.as_bytes())?,
+                Line::Row(row) => match &row.original {
+                    Some((text, header)) if *header == row.header => {
+                        w.write_all(text.as_bytes())?
+                    }
+                    _ => w.write_all(&self.format_row(&row.header)?)?,
+                },
+            }
+            if self.final_newline || i + 1 < self.lines.len() {
+                w.write_all(newline.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The headers in the order of the file.
+    pub fn headers(&self) -> impl Iterator<Item = &SegmentHeader> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Row(row) => Some(&row.header),
+            _ => None,
+        })
+    }
+
+    /// Finds the header for the file at an offset.
+    pub fn get(&self, offset: usize) -> Option<&SegmentHeader> {
+        self.headers().find(|h| h.offset == offset)
+    }
+
+    /// Finds the header for the file at an offset, for editing it.
+    pub fn get_mut(&mut self, offset: usize) -> Option<&mut SegmentHeader> {
+        self.lines.iter_mut().find_map(|line| match line {
+            Line::Row(row) if row.header.offset == offset => Some(&mut row.header),
+            _ => None,
+        })
+    }
+
+    /// Adds a header at the end of the file, or replaces the header with the
+    /// same offset in place.
+    pub fn insert(&mut self, header: SegmentHeader) {
+        match self.get_mut(header.offset) {
+            Some(existing) => *existing = header,
+            None => self.lines.push(Line::Row(Box::new(Row {
+                header,
+                original: None,
+            }))),
+        }
+    }
+
+    /// Removes the header for the file at an offset. The comment lines
+    /// directly before it are removed too if it is the only row in its group,
+    /// i.e., it is not followed by another row, since they would otherwise
+    /// head the rest of the group.
+    pub fn remove(&mut self, offset: usize) -> Option<SegmentHeader> {
+        let i = self
+            .lines
+            .iter()
+            .position(|line| matches!(line, Line::Row(row) if row.header.offset == offset))?;
+        let mut start = i;
+        if !matches!(self.lines.get(i + 1), Some(Line::Row(_))) {
+            while start > 0
+                && let Line::Text(text) = &self.lines[start - 1]
+                && text.starts_with('#')
+            {
+                start -= 1;
+            }
+        }
+        let Line::Row(row) = self.lines.drain(start..=i).next_back()? else {
+            unreachable!();
+        };
+        Some(row.header)
+    }
+
+    /// The column names.
+    pub fn columns(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter()
+    }
+
+    /// Adds a column after the others, if there is none with the name, so
+    /// that changed rows can hold its values. Unchanged rows are not padded.
+    pub fn add_column(&mut self, name: &str) -> Result<()> {
+        if self.columns.iter().any(|c| c.eq_ignore_ascii_case(name)) {
+            return Ok(());
+        }
+        self.columns.push_field(name);
+        let mut w = csv::Writer::from_writer(Vec::new());
+        w.write_record(&self.columns)?;
+        let mut row = String::from_utf8(w.into_inner()?)?;
+        row.truncate(row.trim_end().len());
+        for line in &mut self.lines {
+            if let Line::Columns(text) = line {
+                *text = row;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats a row in the columns of the file, without a line ending.
+    /// Columns which are not known are left empty.
+    fn format_row(&self, header: &SegmentHeader) -> Result<Vec<u8>> {
+        let record = self
+            .columns
+            .iter()
+            .map(|column| match &*column.to_ascii_lowercase() {
+                "path" => header.path.clone(),
+                "offset" => header.offset.to_string().into_bytes(),
+                "length" | "len" => match header.len {
+                    SegmentLen::Auto => b"auto".to_vec(),
+                    SegmentLen::Manual(len) => len.to_string().into_bytes(),
+                },
+                "mode" => opt(header.mode.map(|mode| format!("{mode:04o}"))),
+                "uid" => opt(header.uid),
+                "mtime" => opt(header.mtime),
+                "reference" => opt(header.reference.as_ref()),
+                "confidence" => opt(header.confidence),
+                "notes" => opt(header.notes.as_ref()),
+                "tags" => header.tags.join(" ").into_bytes(),
+                _ => Vec::new(),
+            });
+        let mut w = csv::Writer::from_writer(Vec::new());
+        w.write_record(record)?;
+        let mut row = w.into_inner()?;
+        row.pop();
+        Ok(row)
+    }
+}
+
+fn opt<T: ToString>(value: Option<T>) -> Vec<u8> {
+    value
+        .map(|v| v.to_string().into_bytes())
+        .unwrap_or_default()
+}
+
+impl Default for AnnotationFile {
+    fn default() -> Self {
+        AnnotationFile::new()
+    }
+}
+
+fn parse_record(line: &str) -> Result<StringRecord> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line.as_bytes());
+    match reader.records().next() {
+        Some(record) => Ok(record?),
+        None => bail!("empty CSV row"),
+    }
+}
+
+#[test]
+fn round_trip_s1() {
+    let text = fs::read_to_string("s1-segments.csv").unwrap();
+    let mut file = AnnotationFile::parse(&text).unwrap();
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), text);
+    let mut csv = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(text.as_bytes());
+    let headers = csv
+        .deserialize::<SegmentHeader>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(file.headers().eq(&headers));
+    assert_eq!(file.get(33792).unwrap().len, SegmentLen::Auto);
+
+    file.get_mut(50176).unwrap().len = SegmentLen::Manual(1835);
+    // The comment before /etc/getty also heads /bin/login, so it is kept,
+    // while the comment before /etc/init is only for it.
+    let removed = file.remove(34816).unwrap();
+    assert_eq!(removed.path, b"/etc/getty");
+    let removed = file.remove(33792).unwrap();
+    assert_eq!(removed.path, b"/etc/init");
+    file.insert(SegmentHeader::new(
+        b"/bin/chmod, again".to_vec(),
+        35840,
+        SegmentLen::Manual(82),
+    ));
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("\n/usr/source/s1/ed2.s,50176,1835\n"));
+    assert!(out.ends_with("\"/bin/chmod, again\",35840,82\n"));
+    assert!(!out.contains("/etc/getty,"));
+    assert!(out.contains("\n# Round size up to even:\n/bin/login,38400,1290\n"));
+    assert!(!out.contains("/etc/init,"));
+    assert!(!out.contains("# Possibly from an earlier version of init.s"));
+
+    let reparsed = AnnotationFile::parse(&out).unwrap();
+    assert!(reparsed.headers().eq(file.headers()));
+}
+
+#[test]
+fn optional_columns() {
+    use crate::{archive::Mtime, segment::Confidence, tap::Time};
+
+    let text = "\
+Path,Offset,Length,Mode,Uid,Mtime,Reference,Confidence,Notes,Tags
+/bin/chmod,35840,82,0755,3,1234567,s2-bits:/bin/chmod,1,\"Same as s2, byte for byte\",s2 exact
+/etc/init,33792,auto,,,1972-01-01T00:00:00Z,,,,
+";
+    let mut file = AnnotationFile::parse(text).unwrap();
+    let chmod = file.get(35840).unwrap();
+    assert_eq!(chmod.mode, Some(0o755));
+    assert_eq!(chmod.uid, Some(3));
+    assert_eq!(chmod.mtime, Some(Mtime::V1(Time(1234567))));
+    assert_eq!(chmod.reference.as_deref(), Some("s2-bits:/bin/chmod"));
+    assert_eq!(chmod.confidence, Confidence::new(1.0));
+    assert_eq!(chmod.notes.as_deref(), Some("Same as s2, byte for byte"));
+    assert_eq!(chmod.tags, ["s2", "exact"]);
+    let init = file.get(33792).unwrap();
+    assert_eq!(init.mode, None);
+    assert!(matches!(init.mtime, Some(Mtime::Unix(_))));
+    assert!(init.tags.is_empty());
+
+    // Confidences must be from 0 to 1.
+    for confidence in ["7", "-0.5", "NaN"] {
+        let text = format!("Path,Offset,Length,Confidence\n/bin/chmod,35840,82,{confidence}\n");
+        assert!(AnnotationFile::parse(&text).is_err());
+    }
+
+    // Rewriting changed rows gives the same values.
+    file.get_mut(35840).unwrap().path = b"/bin/chmod2".to_vec();
+    file.get_mut(33792).unwrap().uid = Some(1);
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    let reparsed = AnnotationFile::parse(str::from_utf8(&out).unwrap()).unwrap();
+    assert!(reparsed.headers().eq(file.headers()));
+
+    // Values for columns which the file lacks are dropped, unless added.
+    let mut file = AnnotationFile::new();
+    let mut header = SegmentHeader::new(b"/etc/init".to_vec(), 33792, SegmentLen::Auto);
+    header.mode = Some(0o644);
+    file.insert(header.clone());
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    assert_eq!(
+        str::from_utf8(&out).unwrap(),
+        "Path,Offset,Length\n/etc/init,33792,auto\n"
+    );
+    file.add_column("Mode").unwrap();
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    assert_eq!(
+        str::from_utf8(&out).unwrap(),
+        "Path,Offset,Length,Mode\n/etc/init,33792,auto,0644\n",
+    );
+}
+
+#[test]
+fn line_endings() {
+    let text = "Path,Offset,Length\r\n# Comment\r\n/etc/init,33792,auto\r\n/bin/ls,47616,2010";
+    let mut file = AnnotationFile::parse(text).unwrap();
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    assert_eq!(str::from_utf8(&out).unwrap(), text);
+
+    file.get_mut(33792).unwrap().len = SegmentLen::Manual(424);
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    assert_eq!(
+        str::from_utf8(&out).unwrap(),
+        "Path,Offset,Length\r\n# Comment\r\n/etc/init,33792,424\r\n/bin/ls,47616,2010",
+    );
+}
@@ -0,0 +1,241 @@
+//! A format-agnostic view of the files in a tape, so that exporters can be
+//! written once for tapes with a directory and for segmented tapes.
+
+#![warn(missing_docs)]
+
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use jiff::Timestamp;
+
+use crate::{
+    detect::is_text,
+    dir::{Diagnostic, DirHeader, Format},
+    segment::{Segment, SegmentHeader, SegmentKind, Segmenter},
+    tap::{self, Epoch, Time},
+    tp,
+};
+
+/// A tape which can be viewed as a list of files.
+pub trait TapeArchive {
+    /// The tape which the files were read from.
+    fn tape(&self) -> &[u8];
+
+    /// The files in the tape, in the order of the format.
+    fn entries(&self) -> Vec<TapeEntry<'_>>;
+
+    /// Problems found while reading the tape.
+    fn diagnostics(&self) -> &[Diagnostic] {
+        &[]
+    }
+}
+
+/// A file in a tape.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TapeEntry<'a> {
+    /// The file path. Paths from a directory are usually absolute, while names
+    /// generated for unnamed segments are relative.
+    pub path: Cow<'a, [u8]>,
+    /// The byte offset in the tape of the start of the file.
+    pub offset: usize,
+    /// The file contents.
+    pub data: Cow<'a, [u8]>,
+    /// The POSIX permission bits, if known.
+    pub mode: Option<u16>,
+    /// The user ID, if known.
+    pub uid: Option<u16>,
+    /// The modification time, if known.
+    pub mtime: Option<Mtime>,
+    /// Where the file came from.
+    pub kind: EntryKind<'a>,
+}
+
+/// A modification time, which may need an epoch to be interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mtime {
+    /// A time in the Unix V1 format, relative to an unknown epoch.
+    V1(Time),
+    /// An absolute time.
+    Unix(Timestamp),
+}
+
+/// The provenance of a file in a tape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind<'a> {
+    /// A file in the directory of a tap tape.
+    Tap(&'a tap::Header),
+    /// A file in the directory of a tp tape.
+    Tp(&'a tp::Header),
+    /// A segment of a tape, which is named if a header was given for it.
+    Segment(&'a Segment<'a>, Option<&'a SegmentHeader>),
+}
+
+/// Reads the directory of a tape in a detected format.
+pub fn open_dir(tape: &[u8]) -> Result<Box<dyn TapeArchive + '_>> {
+    match Format::detect(tape) {
+        Some(Format::Tap) => Ok(Box::new(tap::Archive::parse(tape)?)),
+        Some(Format::Tp) => Ok(Box::new(tp::Archive::parse(tape)?)),
+        None => bail!("unrecognized tape format"),
+    }
+}
+
+/// The V1 modification times of the entries with their offsets, for inferring
+/// their epoch.
+pub fn v1_times<'a>(entries: &'a [TapeEntry<'_>]) -> impl Iterator<Item = (usize, Time)> + 'a {
+    entries.iter().filter_map(|e| match e.mtime {
+        Some(Mtime::V1(time)) => Some((e.offset, time)),
+        _ => None,
+    })
+}
+
+impl TapeEntry<'_> {
+    /// The path with any leading slashes removed.
+    pub fn relative_path(&self) -> &[u8] {
+        let mut path = &self.path[..];
+        while let Some(rest) = path.strip_prefix(b"/") {
+            path = rest;
+        }
+        path
+    }
+}
+
+impl Mtime {
+    /// The time as a timestamp, using the given epoch for V1 times.
+    pub fn timestamp(self, epoch: Epoch) -> Timestamp {
+        match self {
+            Mtime::V1(time) => time.timestamp(epoch),
+            Mtime::Unix(t) => t,
+        }
+    }
+}
+
+/// Parses raw V1 ticks or an RFC 3339 timestamp.
+impl FromStr for Mtime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            match s.parse() {
+                Ok(ticks) => Ok(Mtime::V1(Time(ticks))),
+                Err(_) => bail!("V1 time out of range: {s}"),
+            }
+        } else {
+            match s.parse() {
+                Ok(t) => Ok(Mtime::Unix(t)),
+                Err(_) => bail!("invalid time: {s:?}"),
+            }
+        }
+    }
+}
+
+/// Formats raw V1 ticks or an RFC 3339 timestamp, as parsed.
+impl fmt::Display for Mtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mtime::V1(time) => write!(f, "{}", time.0),
+            Mtime::Unix(t) => write!(f, "{t}"),
+        }
+    }
+}
+
+impl fmt::Display for EntryKind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EntryKind::Tap(_) => "tap",
+            EntryKind::Tp(_) => "tp",
+            EntryKind::Segment(_, Some(_)) => "named",
+            EntryKind::Segment(segment, None) => match segment.kind {
+                SegmentKind::Original => "original",
+                SegmentKind::Residue => "residue",
+                SegmentKind::AllNul => "all-nul",
+                SegmentKind::AllFF => "all-ff",
+            },
+        })
+    }
+}
+
+impl TapeArchive for tap::Archive<'_> {
+    fn tape(&self) -> &[u8] {
+        tap::Archive::tape(self)
+    }
+
+    fn entries(&self) -> Vec<TapeEntry<'_>> {
+        self.headers()
+            .map(|h| TapeEntry {
+                path: Cow::Borrowed(h.path()),
+                offset: h.offset(),
+                data: Cow::Borrowed(self.contents(h)),
+                mode: Some(h.mode().to_posix()),
+                uid: Some(h.uid as _),
+                mtime: Some(Mtime::V1(h.mtime())),
+                kind: EntryKind::Tap(h),
+            })
+            .collect()
+    }
+
+    fn diagnostics(&self) -> &[Diagnostic] {
+        tap::Archive::diagnostics(self)
+    }
+}
+
+impl TapeArchive for tp::Archive<'_> {
+    fn tape(&self) -> &[u8] {
+        tp::Archive::tape(self)
+    }
+
+    fn entries(&self) -> Vec<TapeEntry<'_>> {
+        self.headers()
+            .map(|h| TapeEntry {
+                path: Cow::Borrowed(h.path()),
+                offset: h.offset(),
+                data: Cow::Borrowed(self.contents(h)),
+                mode: Some(h.mode().to_posix()),
+                uid: Some(h.uid as _),
+                mtime: Some(Mtime::Unix(h.mtime())),
+                kind: EntryKind::Tp(h),
+            })
+            .collect()
+    }
+
+    fn diagnostics(&self) -> &[Diagnostic] {
+        tp::Archive::diagnostics(self)
+    }
+}
+
+/// Segments are named by their headers, or otherwise by their offset, kind and
+/// whether they are text, as `segments/{offset}{kind}.{txt,bin}`.
+impl TapeArchive for Segmenter<'_> {
+    fn tape(&self) -> &[u8] {
+        Segmenter::tape(self)
+    }
+
+    fn entries(&self) -> Vec<TapeEntry<'_>> {
+        self.segments()
+            .iter()
+            .map(|segment| {
+                let header = self.header_for_offset(segment.offset);
+                let path = if let Some(header) = header {
+                    Cow::Borrowed(&header.path[..])
+                } else {
+                    let ext = if is_text(segment.data) { "txt" } else { "bin" };
+                    let kind = match segment.kind {
+                        SegmentKind::Original => "",
+                        SegmentKind::Residue => ".copy",
+                        SegmentKind::AllNul => ".nul",
+                        SegmentKind::AllFF => ".ff",
+                    };
+                    Cow::Owned(format!("segments/{}{kind}.{ext}", segment.offset).into_bytes())
+                };
+                TapeEntry {
+                    path,
+                    offset: segment.offset,
+                    data: Cow::Borrowed(segment.data),
+                    mode: header.and_then(|h| h.mode),
+                    uid: header.and_then(|h| h.uid),
+                    mtime: header.and_then(|h| h.mtime),
+                    kind: EntryKind::Segment(segment, header),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,248 @@
+//! Byte accounting for a segmented tape, which classifies every byte to
+//! measure how much of the tape has been explained.
+
+#![warn(missing_docs)]
+
+use std::{
+    fmt,
+    io::{self, Write},
+    ops::Range,
+};
+
+use anyhow::Result;
+
+use crate::{
+    interval::IntervalSet,
+    provenance,
+    segment::{SegmentKind, Segmenter},
+};
+
+/// How a range of bytes is accounted for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    /// A file named by a header.
+    Named,
+    /// A segment which is likely a file, but has not been named.
+    Original,
+    /// Residue which was traced to the file it was read from.
+    Residue,
+    /// Blocks of all NUL bytes.
+    AllNul,
+    /// Blocks of all 0xFF bytes.
+    AllFF,
+    /// The boot code at the start of the tape.
+    Boot,
+    /// The directory of a `tap` or `tp` tape.
+    Directory,
+    /// Bytes which are not in any segment, or residue which could not be
+    /// traced.
+    Unexplained,
+}
+
+/// The classification of every byte of a tape.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coverage {
+    /// Classified ranges, sorted by offset, covering the whole tape.
+    ranges: Vec<(Range<usize>, Category)>,
+    block_size: usize,
+}
+
+impl Category {
+    /// All categories, in the order they are reported.
+    pub const ALL: [Category; 8] = [
+        Category::Named,
+        Category::Original,
+        Category::Residue,
+        Category::AllNul,
+        Category::AllFF,
+        Category::Boot,
+        Category::Directory,
+        Category::Unexplained,
+    ];
+
+    /// The character which represents the category in block maps.
+    pub fn symbol(self) -> char {
+        match self {
+            Category::Named => 'N',
+            Category::Original => 'o',
+            Category::Residue => 'r',
+            Category::AllNul => '0',
+            Category::AllFF => 'F',
+            Category::Boot => 'B',
+            Category::Directory => 'D',
+            Category::Unexplained => '?',
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Category::Named => "named",
+            Category::Original => "unnamed",
+            Category::Residue => "residue",
+            Category::AllNul => "all-nul",
+            Category::AllFF => "all-ff",
+            Category::Boot => "boot",
+            Category::Directory => "directory",
+            Category::Unexplained => "unexplained",
+        })
+    }
+}
+
+impl Coverage {
+    /// Classifies the bytes of a segmented tape. The given regions, like the
+    /// boot code, take priority over the segments which overlap them.
+    pub fn new(segmenter: &Segmenter<'_>, regions: &[(Range<usize>, Category)]) -> Result<Self> {
+        let tape = segmenter.tape();
+        let segments = segmenter.segments();
+        let mut covered = IntervalSet::new(0..tape.len());
+        let mut ranges = Vec::new();
+        for (range, category) in regions {
+            let range = range.start.min(tape.len())..range.end.min(tape.len());
+            if !range.is_empty() {
+                covered.insert(range.clone())?;
+                ranges.push((range, *category));
+            }
+        }
+
+        let proven = provenance::trace(tape, segments, segmenter.block_size())
+            .into_iter()
+            .map(|p| (p.residue, p.is_proven()))
+            .collect::<Vec<_>>();
+        let mut disjoint = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            let category = match segment.kind {
+                SegmentKind::Original if segmenter.header_for_offset(segment.offset).is_some() => {
+                    Category::Named
+                }
+                SegmentKind::Original => Category::Original,
+                SegmentKind::Residue if proven.contains(&(i, true)) => Category::Residue,
+                SegmentKind::Residue => Category::Unexplained,
+                SegmentKind::AllNul => Category::AllNul,
+                SegmentKind::AllFF => Category::AllFF,
+            };
+            disjoint.clear();
+            let range = segment.offset..segment.offset + segment.data.len();
+            covered.get_disjoint(range, &mut disjoint);
+            for range in disjoint.drain(..) {
+                covered.insert(range.clone())?;
+                ranges.push((range, category));
+            }
+        }
+
+        covered.get_disjoint(0..tape.len(), &mut disjoint);
+        for range in disjoint {
+            ranges.push((range, Category::Unexplained));
+        }
+        ranges.sort_by_key(|(range, _)| range.start);
+        Ok(Coverage {
+            ranges,
+            block_size: segmenter.block_size(),
+        })
+    }
+
+    /// The classified ranges, sorted by offset, covering the whole tape.
+    pub fn ranges(&self) -> &[(Range<usize>, Category)] {
+        &self.ranges
+    }
+
+    /// The length of the tape.
+    pub fn len(&self) -> usize {
+        self.ranges.last().map_or(0, |(range, _)| range.end)
+    }
+
+    /// Returns whether the tape is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of bytes in each category, in the order of
+    /// [`Category::ALL`].
+    pub fn totals(&self) -> [(Category, usize); 8] {
+        Category::ALL.map(|category| {
+            let total = self
+                .ranges
+                .iter()
+                .filter(|(_, c)| *c == category)
+                .map(|(range, _)| range.len())
+                .sum();
+            (category, total)
+        })
+    }
+
+    /// The category with the most bytes in each block, preferring earlier
+    /// categories in ties.
+    pub fn block_map(&self) -> Vec<Category> {
+        let mut map = Vec::with_capacity(self.len().div_ceil(self.block_size));
+        let mut i = 0;
+        for block_start in (0..self.len()).step_by(self.block_size) {
+            let block_end = (block_start + self.block_size).min(self.len());
+            let mut counts = [0; 8];
+            while let Some((range, category)) = self.ranges.get(i) {
+                let overlap = range.start.max(block_start)..range.end.min(block_end);
+                counts[Category::ALL.iter().position(|c| c == category).unwrap()] += overlap.len();
+                if range.end > block_end {
+                    break;
+                }
+                i += 1;
+            }
+            let max = counts.iter().copied().max().unwrap_or(0);
+            let dominant = counts.iter().position(|&n| n == max).unwrap();
+            map.push(Category::ALL[dominant]);
+        }
+        map
+    }
+
+    /// Writes the totals and percentages of each category, followed by a map
+    /// of the dominant category of each block.
+    pub fn write_report<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let len = self.len().max(1) as f64;
+        writeln!(w, "{:11}  {:>7}  {:>7}", "category", "bytes", "percent")?;
+        for (category, total) in self.totals() {
+            let percent = 100.0 * total as f64 / len;
+            writeln!(w, "{category:11}  {total:7}  {percent:6.2}%")?;
+        }
+        writeln!(w, "{:11}  {:7}  {:6.2}%", "total", self.len(), 100.0)?;
+
+        writeln!(w)?;
+        let legend = Category::ALL
+            .iter()
+            .map(|c| format!("{} {c}", c.symbol()))
+            .collect::<Vec<_>>();
+        writeln!(w, "blocks: {}", legend.join(", "))?;
+        const PER_LINE: usize = 64;
+        for (i, line) in self.block_map().chunks(PER_LINE).enumerate() {
+            let line = line.iter().map(|c| c.symbol()).collect::<String>();
+            writeln!(w, "{:4} {line}", i * PER_LINE)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn coverage_s1() {
+    let tape = std::fs::read("s1-bits").unwrap();
+    let mut segmenter = Segmenter::new(&tape, 512);
+    segmenter.segment_blocks();
+    let coverage = Coverage::new(&segmenter, &[(0..1024, Category::Boot)]).unwrap();
+
+    let ranges = coverage.ranges();
+    assert_eq!(ranges[0], (0..1024, Category::Boot));
+    assert!(ranges.windows(2).all(|w| w[0].0.end == w[1].0.start));
+    assert_eq!(coverage.len(), tape.len());
+    let totals = coverage.totals();
+    assert_eq!(totals.iter().map(|(_, n)| n).sum::<usize>(), tape.len());
+    assert_eq!(totals[0], (Category::Named, 0));
+    assert_eq!(totals[5], (Category::Boot, 1024));
+
+    let map = coverage.block_map();
+    assert_eq!(map.len(), tape.len().div_ceil(512));
+    assert_eq!(map[..2], [Category::Boot, Category::Boot]);
+    // /etc/init is followed by residue from the file before it.
+    assert_eq!(map[33792 / 512], Category::Original);
+    assert_eq!(
+        ranges.iter().find(|(r, _)| r.start == 34216),
+        Some(&(34216..34304, Category::Residue)),
+    );
+}
@@ -1,6 +1,4 @@
-use std::mem;
-
-use crate::util::U16Le;
+use crate::util::{U16Le, View, impl_view};
 
 /// Detects whether the file data is ASCII text.
 pub fn is_text(data: &[u8]) -> bool {
@@ -30,6 +28,17 @@ pub struct AOut {
     pub flag: U16Le,
 }
 
+impl_view!(AOut {
+    magic: U16Le,
+    text_size: U16Le,
+    data_size: U16Le,
+    bss_size: U16Le,
+    symtab_size: U16Le,
+    entry_point: U16Le,
+    unused: U16Le,
+    flag: U16Le,
+});
+
 /// Magic number for an a.out binary or a shell script.
 ///
 /// Follows the logic of [Apout](https://github.com/DoctorWkt/Apout/blob/e88a446ace064f5a41e1a47d9ae8278b83b27a20/aout.c#L89).
@@ -59,7 +68,7 @@ pub enum Magic {
 impl AOut {
     pub fn parse(data: &[u8]) -> Option<&Self> {
         // TODO: Handle V1Normal programs shorter than 8 words.
-        let aout: &AOut = unsafe { mem::transmute(data.first_chunk::<16>()?) };
+        let (aout, _) = AOut::ref_from_prefix(data)?;
         if Magic::from_first(aout.magic.get()).is_some_and(Magic::is_aout) {
             Some(aout)
         } else {
@@ -71,8 +80,10 @@ impl AOut {
         Magic::from_first(self.magic.get()).unwrap()
     }
 
+    /// The size of the file predicted by the header, if known for its magic
+    /// number.
     pub fn file_size(&self) -> Option<usize> {
-        // TODO: Consult documentation for the headers.
+        // TODO: Consult documentation for the other headers.
         match self.magic() {
             Magic::AnyNormal => Some(
                 16 + self.text_size.get() as usize
@@ -88,8 +99,9 @@ impl AOut {
             | Magic::AnySplitID
             | Magic::BsdOverlay
             | Magic::BsdROverlay
-            | Magic::V1Raw => unimplemented!(),
-            Magic::Algol68 | Magic::Shell => None,
+            | Magic::V1Raw
+            | Magic::Algol68
+            | Magic::Shell => None,
         }
     }
 }
@@ -0,0 +1,351 @@
+//! Comparison of two segmentations of the same tape, for reviewing changes to
+//! the heuristics or to the annotations.
+//!
+//! Either side may be read from a listing written by
+//! [`export::write_csv`](crate::export::write_csv) or
+//! [`export::write_json`](crate::export::write_json), so that the output of an
+//! earlier build can be kept as a baseline for regression tests.
+//!
+//! Segments are grouped into the shortest runs which start and end at the same
+//! offsets in both. A run of one segment on each side is the same range, which
+//! may have changed kind or name. Otherwise, a boundary was added, removed or
+//! moved within the run.
+
+#![warn(missing_docs)]
+
+use std::{
+    fmt,
+    io::{self, Write},
+    ops::Range,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{EntryKind, TapeEntry};
+
+/// A difference between two segmentations.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Change {
+    /// What changed.
+    pub change: ChangeKind,
+    /// The byte range in the tape which the change covers.
+    pub offset: usize,
+    /// The length of the range.
+    pub len: usize,
+    /// The segments before, in order.
+    pub old: Vec<Side>,
+    /// The segments after, in order.
+    pub new: Vec<Side>,
+}
+
+/// The kind of a difference between two segmentations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKind {
+    /// One segment was split into several.
+    Split,
+    /// Several segments were merged into one.
+    Merged,
+    /// Boundaries between several segments moved.
+    Moved,
+    /// A segment with the same range changed kind.
+    Kind,
+    /// A segment with the same range was named, renamed or unnamed.
+    Renamed,
+}
+
+/// A segment on one side of a change.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Side {
+    /// The byte offset in the tape.
+    pub offset: usize,
+    /// The length.
+    pub len: usize,
+    /// The kind of segment, or the format of the tape for files from a
+    /// directory.
+    pub kind: String,
+    /// The path given by a header, if it is named.
+    pub path: Option<String>,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            ChangeKind::Split => "split",
+            ChangeKind::Merged => "merged",
+            ChangeKind::Moved => "moved",
+            ChangeKind::Kind => "kind",
+            ChangeKind::Renamed => "renamed",
+        })
+    }
+}
+
+impl Side {
+    /// Describes an entry of a tape.
+    pub fn new(entry: &TapeEntry<'_>) -> Self {
+        let (kind, path) = match entry.kind {
+            EntryKind::Segment(segment, header) => (
+                EntryKind::Segment(segment, None).to_string(),
+                header.map(|h| String::from_utf8_lossy(&h.path).into_owned()),
+            ),
+            EntryKind::Tap(_) | EntryKind::Tp(_) => (
+                entry.kind.to_string(),
+                Some(String::from_utf8_lossy(&entry.path).into_owned()),
+            ),
+        };
+        Side {
+            offset: entry.offset,
+            len: entry.data.len(),
+            kind,
+            path,
+        }
+    }
+
+    /// The byte range in the tape.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+/// A row of a listing written by [`export`](crate::export), with only the
+/// columns needed for comparing.
+#[derive(Deserialize)]
+struct Listed {
+    path: String,
+    offset: usize,
+    len: usize,
+    kind: String,
+}
+
+/// Describes the entries of a tape, in order.
+pub fn sides(entries: &[TapeEntry<'_>]) -> Vec<Side> {
+    entries.iter().map(Side::new).collect()
+}
+
+/// Reads a listing of the entries of a tape, as CSV or JSON written by
+/// [`export`](crate::export), and sorts it by offset.
+///
+/// Named segments are listed with the kind `named`, but are always original
+/// segments. Unnamed segments are listed with generated names, which are
+/// dropped.
+pub fn read_listing(text: &str) -> Result<Vec<Side>> {
+    let listed = if text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<Listed>>(text)?
+    } else {
+        csv::Reader::from_reader(text.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<Listed>, _>>()?
+    };
+    let mut sides = listed
+        .into_iter()
+        .map(|l| {
+            let (kind, named) = match &*l.kind {
+                "named" => ("original".to_owned(), true),
+                "tap" | "tp" => (l.kind, true),
+                _ => (l.kind, false),
+            };
+            Side {
+                offset: l.offset,
+                len: l.len,
+                kind,
+                path: named.then_some(l.path),
+            }
+        })
+        .collect::<Vec<_>>();
+    sides.sort_by_key(|s| s.offset);
+    Ok(sides)
+}
+
+/// Compares two segmentations of the same tape, with segments sorted by offset
+/// and not overlapping, and returns the changes in tape order.
+pub fn diff(old: &[Side], new: &[Side]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        let (start_i, start_j) = (i, j);
+        let (mut end_old, mut end_new) = (old[i].range().end, new[j].range().end);
+        i += 1;
+        j += 1;
+        while end_old != end_new {
+            if end_old < end_new && i < old.len() {
+                end_old = old[i].range().end;
+                i += 1;
+            } else if end_new < end_old && j < new.len() {
+                end_new = new[j].range().end;
+                j += 1;
+            } else {
+                break;
+            }
+        }
+        push_changes(&mut changes, &old[start_i..i], &new[start_j..j]);
+    }
+    if i < old.len() || j < new.len() {
+        push_changes(&mut changes, &old[i..], &new[j..]);
+    }
+    changes
+}
+
+/// Adds the changes for a run of segments which start and end together.
+fn push_changes(changes: &mut Vec<Change>, old: &[Side], new: &[Side]) {
+    let start = old.iter().chain(new).map(|s| s.offset).min().unwrap_or(0);
+    let end = old
+        .iter()
+        .chain(new)
+        .map(|s| s.range().end)
+        .max()
+        .unwrap_or(0);
+    let mut push = |change| {
+        changes.push(Change {
+            change,
+            offset: start,
+            len: end - start,
+            old: old.to_vec(),
+            new: new.to_vec(),
+        })
+    };
+    match (old, new) {
+        ([a], [b]) if a.range() == b.range() => {
+            if a.kind != b.kind {
+                push(ChangeKind::Kind);
+            }
+            if a.path != b.path {
+                push(ChangeKind::Renamed);
+            }
+        }
+        ([_], _) if new.len() > 1 => push(ChangeKind::Split),
+        (_, [_]) if old.len() > 1 => push(ChangeKind::Merged),
+        _ => push(ChangeKind::Moved),
+    }
+}
+
+/// Writes changes as text, one per line.
+pub fn write_text<W: Write>(changes: &[Change], mut w: W) -> io::Result<()> {
+    for change in changes {
+        writeln!(
+            w,
+            "{:8} {}..{}: {} -> {}",
+            change.change,
+            change.offset,
+            change.offset + change.len,
+            format_sides(&change.old),
+            format_sides(&change.new),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes changes as a JSON array.
+pub fn write_json<W: Write>(changes: &[Change], mut w: W) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut w, changes)?;
+    writeln!(w)
+}
+
+fn format_sides(sides: &[Side]) -> String {
+    let sides = sides
+        .iter()
+        .map(|s| match &s.path {
+            Some(path) => format!("{}+{} {} {path:?}", s.offset, s.len, s.kind),
+            None => format!("{}+{} {}", s.offset, s.len, s.kind),
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", sides.join(", "))
+}
+
+#[test]
+fn diff_s1() {
+    use crate::{
+        archive::TapeArchive,
+        segment::{SegmentHeader, SegmentLen, Segmenter},
+    };
+
+    let tape = std::fs::read("s1-bits").unwrap();
+    let segment = |headers: &[SegmentHeader]| {
+        let mut segmenter = Segmenter::new(&tape, 512);
+        for header in headers {
+            segmenter.add_header(header.clone()).unwrap();
+        }
+        segmenter.segment_blocks();
+        segmenter
+    };
+    let ed2 = |len| SegmentHeader::new(b"/usr/source/s1/ed2.s".to_vec(), 50176, len);
+    let auto = sides(&segment(&[]).entries());
+    let manual = segment(&[ed2(SegmentLen::Manual(1834))]);
+    let manual_entries = manual.entries();
+    let manual = sides(&manual_entries);
+    let short = sides(&segment(&[ed2(SegmentLen::Manual(1800))]).entries());
+
+    assert_eq!(diff(&auto, &auto), []);
+
+    // Naming a segment which was already found only renames it.
+    let changes = diff(&auto, &manual);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].change, ChangeKind::Renamed);
+    assert_eq!(changes[0].offset, 50176);
+    assert_eq!(
+        changes[0].new[0].path.as_deref(),
+        Some("/usr/source/s1/ed2.s")
+    );
+
+    // Shortening it moves the boundary with its residue.
+    let changes = diff(&manual, &short);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].change, ChangeKind::Moved);
+    assert_eq!(
+        changes[0].offset..changes[0].offset + changes[0].len,
+        50176..52224
+    );
+    let ranges = |sides: &[Side]| sides.iter().map(Side::range).collect::<Vec<_>>();
+    assert_eq!(ranges(&changes[0].old), [50176..52010, 52010..52224]);
+    assert_eq!(ranges(&changes[0].new), [50176..51976, 51976..52224]);
+
+    let mut json = Vec::new();
+    write_json(&changes, &mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json[0]["change"], "moved");
+    assert_eq!(json[0]["new"][1]["kind"], "residue");
+
+    // Listings saved by an earlier build read back as the same segments.
+    let mut csv = Vec::new();
+    crate::export::write_csv(&manual_entries, crate::tap::Epoch::Y1972, &mut csv).unwrap();
+    let listed = read_listing(str::from_utf8(&csv).unwrap()).unwrap();
+    assert_eq!(listed, manual);
+    let mut json = Vec::new();
+    crate::export::write_json(&manual_entries, crate::tap::Epoch::Y1972, &mut json).unwrap();
+    let listed = read_listing(str::from_utf8(&json).unwrap()).unwrap();
+    assert_eq!(listed, manual);
+}
+
+#[test]
+fn diff_moved() {
+    let side = |range: Range<usize>| Side {
+        offset: range.start,
+        len: range.len(),
+        kind: "original".to_owned(),
+        path: None,
+    };
+
+    // One segment which starts later, leaving a gap, has moved.
+    let changes = diff(&[side(0..100)], &[side(10..100)]);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].change, ChangeKind::Moved);
+    assert_eq!(
+        changes[0].offset..changes[0].offset + changes[0].len,
+        0..100
+    );
+
+    // So has one which ends at a different tape end.
+    let changes = diff(
+        &[side(0..100), side(100..200)],
+        &[side(0..100), side(100..150)],
+    );
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].change, ChangeKind::Moved);
+    assert_eq!(changes[0].offset, 100);
+
+    // Splits and merges still need several segments on one side.
+    let split = diff(&[side(0..100)], &[side(0..40), side(40..100)]);
+    assert_eq!(split[0].change, ChangeKind::Split);
+    let merged = diff(&[side(0..40), side(40..100)], &[side(0..100)]);
+    assert_eq!(merged[0].change, ChangeKind::Merged);
+}
@@ -0,0 +1,342 @@
+//! Directory-based tape formats, i.e., `tap` from the 1st and 2nd Editions and
+//! `tp` from the 3rd Edition onwards.
+//!
+//! Both start with a boot block, followed by a directory of 64-byte file
+//! headers whose words sum to zero, then the file contents in 512-byte blocks.
+//! They differ in the layout of the headers.
+
+#![warn(missing_docs)]
+
+use std::{collections::HashMap, fmt, ops::Range};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    tap, tp,
+    util::{View, sum_words},
+};
+
+/// A file header in the directory of a tape.
+pub trait DirHeader: fmt::Debug + Sized {
+    /// Reinterprets raw bytes as a header.
+    fn from_bytes(raw: &[u8; 64]) -> &Self;
+
+    /// The raw bytes of this header.
+    fn as_bytes(&self) -> &[u8; 64];
+
+    /// The file path.
+    fn path(&self) -> &[u8];
+
+    /// The byte offsets in the tape of the file contents.
+    fn range(&self) -> Range<usize>;
+
+    /// The bytes of this header which are unused by the format.
+    fn unused(&self) -> &[u8];
+
+    /// The byte offset in the tape of the start of the file.
+    fn offset(&self) -> usize {
+        self.range().start
+    }
+
+    /// The checksum of this header.
+    fn cksum(&self) -> u16 {
+        u16::from_le_bytes(self.as_bytes()[62..].try_into().unwrap())
+    }
+
+    /// Validates the header against its checksum.
+    fn valid(&self) -> bool {
+        sum_words(self.as_bytes()) == 0
+    }
+
+    /// Computes the checksum which makes the words of this header sum to zero.
+    fn compute_cksum(&self) -> u16 {
+        sum_words(&self.as_bytes()[..62]).wrapping_neg()
+    }
+}
+
+/// A directory-based tape format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `tap` from the 1st and 2nd Editions.
+    Tap,
+    /// `tp` from the 3rd Edition onwards.
+    Tp,
+}
+
+/// The directory of a tape.
+///
+/// The directory starts after the boot block and extends up to the first block
+/// of file contents. Problems found while reading it are collected as
+/// [diagnostics](Diagnostic), instead of silently skipping entries.
+pub struct Archive<'a, H> {
+    tape: &'a [u8],
+    /// The byte offsets of the directory.
+    dir: Range<usize>,
+    /// The valid headers, in directory order.
+    entries: Vec<Entry<'a, H>>,
+    /// Problems found in the directory.
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A header in the directory of a tape.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry<'a, H> {
+    /// The byte offset of the header in the tape.
+    pub offset: usize,
+    /// The header.
+    pub header: &'a H,
+}
+
+/// A problem found in the directory of a tape.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The byte offset of the header in the tape.
+    pub offset: usize,
+    /// The kind of problem.
+    pub kind: DiagnosticKind,
+}
+
+/// A kind of problem found in the directory of a tape.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The words of the header do not sum to zero. The sum is recorded.
+    Checksum(u16),
+    /// The unused bytes are not all zero.
+    UnusedNonZero,
+    /// The path is the same as that of the header at the given offset.
+    DuplicatePath(usize),
+    /// The contents start within the boot block or the directory.
+    InDirectory,
+    /// The contents extend past the end of the tape.
+    OutOfBounds,
+    /// The contents overlap with those of the header at the given offset.
+    Overlap(usize),
+}
+
+impl Format {
+    /// Detects the format of a tape from the valid headers in its first
+    /// directory block.
+    ///
+    /// In `tap` headers, every byte after the block number is unused, while in
+    /// `tp` headers, the block number follows the time, which is only zero at
+    /// the very start of its epoch.
+    pub fn detect(tape: &[u8]) -> Option<Self> {
+        let block = tape.get(512..1024)?;
+        let headers = block
+            .chunks_exact(64)
+            .filter(|raw| raw.iter().any(|&b| b != 0) && sum_words(raw) == 0)
+            .collect::<Vec<_>>();
+        if headers.is_empty() {
+            None
+        } else if headers
+            .iter()
+            .all(|raw| raw[42..62].iter().all(|&b| b == 0))
+        {
+            Some(Format::Tap)
+        } else if headers
+            .iter()
+            .all(|raw| raw[46..62].iter().all(|&b| b == 0) && raw[44..46] != [0, 0])
+        {
+            Some(Format::Tp)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, H: DirHeader> Archive<'a, H> {
+    /// Reads the directory of a tape. Fails if the first directory block does
+    /// not start with a valid header.
+    pub fn parse(tape: &'a [u8]) -> Result<Self> {
+        let Some(first) = tape.get(512..576) else {
+            bail!("tape too short for a directory");
+        };
+        let first = H::from_bytes(first.try_into().unwrap());
+        if first.as_bytes().iter().all(|&b| b == 0) || !first.valid() {
+            bail!("no directory");
+        }
+
+        let mut archive = Archive {
+            tape,
+            dir: 512..tape.len(),
+            entries: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        let mut paths = HashMap::new();
+        let mut offset = 512;
+        while offset + 64 <= archive.dir.end {
+            let raw: &[u8; 64] = tape[offset..offset + 64].try_into().unwrap();
+            offset += 64;
+            if raw.iter().all(|&b| b == 0) {
+                continue;
+            }
+            let header = H::from_bytes(raw);
+            let entry_offset = offset - 64;
+            if !header.valid() {
+                let sum = header.cksum().wrapping_sub(header.compute_cksum());
+                archive.diag(entry_offset, DiagnosticKind::Checksum(sum));
+                continue;
+            }
+            if !header.unused().iter().all(|&b| b == 0) {
+                archive.diag(entry_offset, DiagnosticKind::UnusedNonZero);
+            }
+            if let Some(&prev) = paths.get(header.path()) {
+                archive.diag(entry_offset, DiagnosticKind::DuplicatePath(prev));
+            } else {
+                paths.insert(header.path(), entry_offset);
+            }
+            if header.offset() < offset {
+                archive.diag(entry_offset, DiagnosticKind::InDirectory);
+            } else {
+                archive.dir.end = archive.dir.end.min(header.offset());
+            }
+            if header.range().end > tape.len() {
+                archive.diag(entry_offset, DiagnosticKind::OutOfBounds);
+            }
+            archive.entries.push(Entry {
+                offset: entry_offset,
+                header,
+            });
+        }
+
+        let mut by_offset = archive
+            .entries
+            .iter()
+            .filter(|e| !e.header.range().is_empty())
+            .copied()
+            .collect::<Vec<_>>();
+        by_offset.sort_by_key(|e| (e.header.offset(), e.offset));
+        let mut last: Option<Entry<'_, H>> = None;
+        for entry in by_offset {
+            if let Some(last) = last
+                && entry.header.offset() < last.header.range().end
+            {
+                archive.diag(entry.offset, DiagnosticKind::Overlap(last.offset));
+            }
+            if last.is_none_or(|l| entry.header.range().end > l.header.range().end) {
+                last = Some(entry);
+            }
+        }
+        archive.diagnostics.sort_by_key(|d| d.offset);
+        Ok(archive)
+    }
+
+    fn diag(&mut self, offset: usize, kind: DiagnosticKind) {
+        self.diagnostics.push(Diagnostic { offset, kind });
+    }
+
+    /// The tape which this directory was read from.
+    pub fn tape(&self) -> &'a [u8] {
+        self.tape
+    }
+
+    /// The byte offsets of the directory.
+    pub fn dir_range(&self) -> Range<usize> {
+        self.dir.clone()
+    }
+
+    /// The valid headers, in directory order.
+    pub fn entries(&self) -> &[Entry<'a, H>] {
+        &self.entries
+    }
+
+    /// Iterates the valid headers, in directory order.
+    pub fn headers(&self) -> impl Iterator<Item = &'a H> + '_ {
+        self.entries.iter().map(|e| e.header)
+    }
+
+    /// Problems found in the directory, sorted by offset.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The contents of the file for a header, clamped to the tape.
+    pub fn contents(&self, header: &H) -> &'a [u8] {
+        let range = header.range();
+        let end = range.end.min(self.tape.len());
+        &self.tape[range.start.min(end)..end]
+    }
+}
+
+impl<H> Clone for Entry<'_, H> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<H> Copy for Entry<'_, H> {}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Tap => "tap",
+            Format::Tp => "tp",
+        })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "header at offset {}: ", self.offset)?;
+        match self.kind {
+            DiagnosticKind::Checksum(sum) => write!(f, "checksum sums to {sum:#06x}"),
+            DiagnosticKind::UnusedNonZero => write!(f, "unused bytes are not zero"),
+            DiagnosticKind::DuplicatePath(prev) => {
+                write!(f, "duplicate path of header at offset {prev}")
+            }
+            DiagnosticKind::InDirectory => write!(f, "contents start within the directory"),
+            DiagnosticKind::OutOfBounds => write!(f, "contents extend past the end of the tape"),
+            DiagnosticKind::Overlap(prev) => {
+                write!(f, "contents overlap those of header at offset {prev}")
+            }
+        }
+    }
+}
+
+impl DirHeader for tap::Header {
+    fn from_bytes(raw: &[u8; 64]) -> &Self {
+        raw.into()
+    }
+
+    fn as_bytes(&self) -> &[u8; 64] {
+        tap::Header::as_bytes(self)
+    }
+
+    fn path(&self) -> &[u8] {
+        tap::Header::path(self)
+    }
+
+    fn range(&self) -> Range<usize> {
+        tap::Header::range(self)
+    }
+
+    fn unused(&self) -> &[u8] {
+        &self.unused
+    }
+}
+
+impl DirHeader for tp::Header {
+    fn from_bytes(raw: &[u8; 64]) -> &Self {
+        raw.into()
+    }
+
+    fn as_bytes(&self) -> &[u8; 64] {
+        View::as_bytes(self).try_into().unwrap()
+    }
+
+    fn path(&self) -> &[u8] {
+        let mut path = &self.path[..];
+        while let Some((&0, rest)) = path.split_last() {
+            path = rest;
+        }
+        path
+    }
+
+    fn range(&self) -> Range<usize> {
+        let offset = self.block() as usize * 512;
+        offset..offset + self.size() as usize
+    }
+
+    fn unused(&self) -> &[u8] {
+        &self.unused
+    }
+}
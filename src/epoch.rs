@@ -0,0 +1,166 @@
+//! Inference of the epoch of Unix V1 timestamps.
+//!
+//! Changing the epoch shifts every timestamp by the same whole number of days,
+//! but where they land on the calendar differs. 1970 starts on a Thursday,
+//! 1971 on a Friday and 1972 on a Saturday, but 1972 is a leap year, so 1973
+//! starts on a Monday, two weekdays later. The same time is therefore one, two
+//! or four weekdays later in the 1971, 1972 or 1973 epoch than in 1970, and a
+//! working week under one epoch is partly a weekend under another. Timestamps
+//! are clustered into sessions of work, so that a batch of files copied at
+//! once counts as one event, and sessions on weekends count against an epoch.
+//! Together with the date an archive was written, this is enough to rank the
+//! candidates.
+//!
+//! The modification times are also compared with the order of the files'
+//! blocks on the tape. This is the same in every epoch, so it does not rank
+//! them, but many files older than the one laid out before them show that the
+//! tape was assembled from copies and its times say little about when it was
+//! written.
+
+#![warn(missing_docs)]
+
+use jiff::{SignedDuration, Timestamp, civil::Weekday, tz::TimeZone};
+
+use crate::tap::{Epoch, Time};
+
+/// The longest gap between timestamps in the same session.
+pub const SESSION_GAP: SignedDuration = SignedDuration::from_hours(2);
+
+/// Evidence for the plausibility of an epoch for a set of timestamps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochEvidence {
+    /// The candidate epoch.
+    pub epoch: Epoch,
+    /// The number of timestamps.
+    pub total: usize,
+    /// The number of timestamps after the date the archive was written. These
+    /// are impossible in the correct epoch.
+    pub future: usize,
+    /// The number of timestamps on a Saturday or Sunday.
+    pub weekend: usize,
+    /// The number of sessions, which are runs of timestamps separated by less
+    /// than [`SESSION_GAP`].
+    pub sessions: usize,
+    /// The number of sessions which start on a Saturday or Sunday.
+    pub weekend_sessions: usize,
+    /// The number of files, in the order of their blocks, which were modified
+    /// before the file laid out before them.
+    pub out_of_order: usize,
+    /// The earliest timestamp, if any.
+    pub first: Option<Timestamp>,
+    /// The latest timestamp, if any.
+    pub last: Option<Timestamp>,
+}
+
+/// Scores each epoch for a set of timestamps, such as the modification times of
+/// tap headers, with the offsets of the files they belong to, and returns them
+/// ranked from most to least plausible.
+///
+/// Epochs are ranked first by the number of timestamps after `archive_date`,
+/// if known, then by the number of sessions on weekends, then by the number of
+/// timestamps on weekends. Ties are broken in favor of 1972, the epoch
+/// [`Time`](crate::tap::Time) defaults to.
+pub fn rank_epochs(
+    times: impl IntoIterator<Item = (usize, Time)>,
+    archive_date: Option<Timestamp>,
+) -> Vec<EpochEvidence> {
+    let mut times = times.into_iter().collect::<Vec<_>>();
+    times.sort_by_key(|&(offset, _)| offset);
+    let out_of_order = times.windows(2).filter(|w| w[1].1 < w[0].1).count();
+    let mut times = times.into_iter().map(|(_, time)| time).collect::<Vec<_>>();
+    times.sort();
+
+    let mut ranked = Epoch::ALL
+        .iter()
+        .map(|&epoch| {
+            let mut evidence = EpochEvidence {
+                epoch,
+                total: times.len(),
+                future: 0,
+                weekend: 0,
+                sessions: 0,
+                weekend_sessions: 0,
+                out_of_order,
+                first: None,
+                last: None,
+            };
+            for time in &times {
+                let t = time.timestamp(epoch);
+                if archive_date.is_some_and(|date| t > date) {
+                    evidence.future += 1;
+                }
+                let weekend = is_weekend(t);
+                if weekend {
+                    evidence.weekend += 1;
+                }
+                if evidence
+                    .last
+                    .is_none_or(|last| t.duration_since(last) >= SESSION_GAP)
+                {
+                    evidence.sessions += 1;
+                    if weekend {
+                        evidence.weekend_sessions += 1;
+                    }
+                }
+                evidence.first.get_or_insert(t);
+                evidence.last = Some(t);
+            }
+            evidence
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by_key(|e| {
+        (
+            e.future,
+            e.weekend_sessions,
+            e.weekend,
+            e.epoch != Epoch::Y1972,
+        )
+    });
+    ranked
+}
+
+fn is_weekend(t: Timestamp) -> bool {
+    matches!(
+        t.to_zoned(TimeZone::UTC).weekday(),
+        Weekday::Saturday | Weekday::Sunday
+    )
+}
+
+/// Infers the most plausible epoch for a set of timestamps.
+pub fn infer_epoch(
+    times: impl IntoIterator<Item = (usize, Time)>,
+    archive_date: Option<Timestamp>,
+) -> Epoch {
+    rank_epochs(times, archive_date)[0].epoch
+}
+
+#[test]
+fn rank_s2() {
+    use crate::tap::Archive;
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = Archive::parse(&tape).unwrap();
+    let times = || archive.headers().map(|h| (h.offset(), h.mtime()));
+    let ranked = rank_epochs(times(), None);
+    let order = ranked.iter().map(|e| e.epoch).collect::<Vec<_>>();
+    assert_eq!(
+        order,
+        [Epoch::Y1972, Epoch::Y1971, Epoch::Y1973, Epoch::Y1970],
+    );
+    assert_eq!(ranked[0].total, 95);
+    assert_eq!(ranked[0].weekend, 17);
+    assert_eq!(ranked[0].sessions, 33);
+    assert_eq!(ranked[0].weekend_sessions, 4);
+    assert!(ranked.iter().all(|e| e.out_of_order == 46));
+
+    // Only the 1970 and 1971 epochs put every timestamp before 1972-02-07.
+    let date = "1972-02-07T00:00:00Z".parse().unwrap();
+    let ranked = rank_epochs(times(), Some(date));
+    assert_eq!(ranked[0].epoch, Epoch::Y1971);
+    assert_eq!(ranked[0].future, 0);
+}
+
+#[test]
+fn rank_empty() {
+    assert_eq!(infer_epoch([], None), Epoch::Y1972);
+}
@@ -0,0 +1,266 @@
+//! Exporters for the files of any [`TapeArchive`] to tar, CSV and JSON.
+//! Exporting to a directory tree is in [`extract`](crate::extract).
+//!
+//! [`TapeArchive`]: crate::archive::TapeArchive
+
+#![warn(missing_docs)]
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    archive::{EntryKind, Mtime, TapeEntry},
+    pax,
+    segment::{Boundary, Confidence},
+    tap::Epoch,
+};
+
+/// A row of a CSV or JSON listing of the files in a tape.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Record {
+    /// The file path, with invalid UTF-8 replaced.
+    pub path: String,
+    /// The byte offset in the tape of the start of the file.
+    pub offset: usize,
+    /// The length of the file contents.
+    pub len: usize,
+    /// The POSIX permission bits in octal, if known.
+    pub mode: Option<String>,
+    /// The user ID, if known.
+    pub uid: Option<u16>,
+    /// The modification time in RFC 3339 format, if known.
+    pub mtime: Option<String>,
+    /// Where the file came from.
+    pub kind: String,
+    /// For segments, the confidence in the start boundary.
+    pub start_confidence: Option<f64>,
+    /// For segments, the evidence for the start boundary.
+    pub start_evidence: Option<String>,
+    /// For segments, the confidence in the end boundary.
+    pub end_confidence: Option<f64>,
+    /// For segments, the evidence for the end boundary.
+    pub end_evidence: Option<String>,
+    /// For annotated segments, the known file which it was matched with.
+    pub reference: Option<String>,
+    /// For annotated segments, the confidence in the identification.
+    pub confidence: Option<f64>,
+    /// For annotated segments, notes on the file.
+    pub notes: Option<String>,
+    /// For annotated segments, tags separated by spaces.
+    pub tags: Option<String>,
+}
+
+impl Record {
+    /// Converts an entry to a record, with V1 times in the given epoch.
+    pub fn new(entry: &TapeEntry<'_>, epoch: Epoch) -> Self {
+        let (segment, header) = match entry.kind {
+            EntryKind::Segment(segment, header) => (Some(segment), header),
+            _ => (None, None),
+        };
+        let evidence = |b: &Boundary| {
+            b.evidence
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("+")
+        };
+        Record {
+            path: String::from_utf8_lossy(&entry.path).into_owned(),
+            offset: entry.offset,
+            len: entry.data.len(),
+            mode: entry.mode.map(|mode| format!("{mode:04o}")),
+            uid: entry.uid,
+            mtime: entry.mtime.map(|t| t.timestamp(epoch).to_string()),
+            kind: entry.kind.to_string(),
+            start_confidence: segment.map(|s| round(s.start.confidence())),
+            start_evidence: segment.map(|s| evidence(&s.start)),
+            end_confidence: segment.map(|s| round(s.end.confidence())),
+            end_evidence: segment.map(|s| evidence(&s.end)),
+            reference: header.and_then(|h| h.reference.clone()),
+            confidence: header.and_then(|h| h.confidence).map(Confidence::get),
+            notes: header.and_then(|h| h.notes.clone()),
+            tags: header
+                .filter(|h| !h.tags.is_empty())
+                .map(|h| h.tags.join(" ")),
+        }
+    }
+}
+
+/// Rounds a confidence to 3 decimal places.
+fn round(confidence: f64) -> f64 {
+    (confidence * 1000.0).round() / 1000.0
+}
+
+/// Writes the entries to a tar archive, with V1 times in the given epoch, or
+/// raw when `None`.
+///
+/// Files from tap tapes are written with PAX extended attributes, which
+/// preserve their V1 metadata. Others are written with old-style headers and,
+/// when not known, a mode of 0644. Raw V1 times are only kept for tap files;
+/// other files with V1 times get an mtime of zero.
+pub fn write_tar<W: Write>(entries: &[TapeEntry<'_>], epoch: Option<Epoch>, w: W) -> io::Result<W> {
+    let mut tar = tar::Builder::new(w);
+    for entry in entries {
+        if let EntryKind::Tap(header) = entry.kind {
+            pax::append(&mut tar, header, &entry.data, epoch)?;
+            continue;
+        }
+        let mut h = tar::Header::new_old();
+        h.set_path(OsStr::from_bytes(entry.relative_path()))?;
+        h.set_mode(entry.mode.unwrap_or(0o644) as _);
+        if let Some(uid) = entry.uid {
+            h.set_uid(uid as _);
+        }
+        match (entry.mtime, epoch) {
+            (Some(Mtime::V1(time)), Some(epoch)) => h.set_mtime(time.seconds(epoch) as _),
+            (Some(Mtime::Unix(t)), _) => h.set_mtime(t.as_second() as _),
+            _ => {}
+        }
+        h.set_size(entry.data.len() as _);
+        h.set_cksum();
+        tar.append(&h, &*entry.data)?;
+    }
+    tar.into_inner()
+}
+
+/// Writes a tar archive of the entries for each epoch, to the given path with
+/// the year inserted before the extension, e.g., `s2-files-1972.tar`. Returns
+/// the paths written.
+pub fn write_epoch_tars(entries: &[TapeEntry<'_>], path: &Path) -> Result<Vec<PathBuf>> {
+    let stem = path.file_stem().unwrap_or_default().to_owned();
+    let mut paths = Vec::new();
+    for epoch in Epoch::ALL {
+        let mut name = stem.clone();
+        name.push(format!("-{}", epoch.year()));
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let path = path.with_file_name(name);
+        write_tar(entries, Some(epoch), File::create(&path)?)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Writes a CSV listing of the entries, with V1 times in the given epoch.
+pub fn write_csv<W: Write>(entries: &[TapeEntry<'_>], epoch: Epoch, w: W) -> Result<()> {
+    let mut csv = csv::Writer::from_writer(w);
+    for entry in entries {
+        csv.serialize(Record::new(entry, epoch))?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Writes a JSON listing of the entries, with V1 times in the given epoch.
+pub fn write_json<W: Write>(entries: &[TapeEntry<'_>], epoch: Epoch, mut w: W) -> Result<()> {
+    let records = entries
+        .iter()
+        .map(|entry| Record::new(entry, epoch))
+        .collect::<Vec<_>>();
+    serde_json::to_writer_pretty(&mut w, &records)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+#[test]
+fn export_s2() {
+    use crate::archive::open_dir;
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = open_dir(&tape).unwrap();
+    let entries = archive.entries();
+
+    let mut csv = Vec::new();
+    write_csv(&entries, Epoch::Y1972, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some(
+            "path,offset,len,mode,uid,mtime,kind,\
+             start_confidence,start_evidence,end_confidence,end_evidence,\
+             reference,confidence,notes,tags"
+        ),
+    );
+    assert_eq!(
+        lines.next(),
+        Some("/bin/chmod,12800,82,0755,3,1972-01-17T17:53:35.433333333Z,tap,,,,,,,,"),
+    );
+    assert_eq!(lines.count(), 94);
+
+    let mut json = Vec::new();
+    write_json(&entries, Epoch::Y1972, &mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json[0]["path"], "/bin/chmod");
+    assert_eq!(json[94]["kind"], "tap");
+
+    let raw = write_tar(&entries, None, Vec::new()).unwrap();
+    let mut tar = tar::Archive::new(&raw[..]);
+    let mut tar_entries = tar.entries().unwrap();
+    let mut first = tar_entries.next().unwrap().unwrap();
+    assert_eq!(first.header().mtime().unwrap(), 0);
+    let exts = first.pax_extensions().unwrap().unwrap();
+    let keys = exts
+        .map(|ext| ext.unwrap().key().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert!(keys.iter().any(|key| key == pax::V1_MTIME));
+    assert!(
+        !keys
+            .iter()
+            .any(|key| key == pax::V1_EPOCH || key == "mtime")
+    );
+}
+
+#[test]
+fn export_annotated_s1() {
+    use crate::{
+        annotation::AnnotationFile,
+        archive::TapeArchive,
+        segment::{SegmentHeader, SegmentLen, Segmenter},
+        tap::Time,
+    };
+
+    let tape = std::fs::read("s1-bits").unwrap();
+    let mut file = AnnotationFile::new();
+    file.insert(SegmentHeader {
+        mode: Some(0o755),
+        uid: Some(3),
+        mtime: Some(Mtime::V1(Time(60))),
+        notes: Some("Same as s2".to_owned()),
+        tags: vec!["s2".to_owned(), "exact".to_owned()],
+        ..SegmentHeader::new(b"/bin/chmod".to_vec(), 35840, SegmentLen::Manual(82))
+    });
+    let mut segmenter = Segmenter::new(&tape, 512);
+    for header in file.headers() {
+        segmenter.add_header(header.clone()).unwrap();
+    }
+    segmenter.segment_blocks();
+    let entries = segmenter.entries();
+    let chmod = entries.iter().find(|e| e.offset == 35840).unwrap();
+
+    let record = Record::new(chmod, Epoch::Y1972);
+    assert_eq!(record.mode.as_deref(), Some("0755"));
+    assert_eq!(record.notes.as_deref(), Some("Same as s2"));
+    assert_eq!(record.tags.as_deref(), Some("s2 exact"));
+
+    let tar = write_tar(std::slice::from_ref(chmod), Some(Epoch::Y1972), Vec::new()).unwrap();
+    let mut tar = tar::Archive::new(&tar[..]);
+    let entry = tar.entries().unwrap().next().unwrap().unwrap();
+    let header = entry.header();
+    assert_eq!(header.mode().unwrap(), 0o755);
+    assert_eq!(header.uid().unwrap(), 3);
+    assert_eq!(
+        header.mtime().unwrap(),
+        Epoch::Y1972.timestamp().as_second() as u64 + 1
+    );
+}
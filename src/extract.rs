@@ -0,0 +1,153 @@
+//! Extraction of the files of a tape to a directory tree.
+
+#![warn(missing_docs)]
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs::{self, OpenOptions, Permissions},
+    io::Write,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{archive::TapeEntry, tap::Epoch, util::Bytes};
+
+/// Writes the files of a tape into the directory `root`, creating the
+/// directories implied by their paths.
+///
+/// Permissions and modification times are set when known, with V1 times in the
+/// given epoch. The setuid, setgid and sticky bits are dropped unless
+/// `preserve_special` is set. Paths are made relative to `root` and all are validated before
+/// anything is written, including that no two entries have the same path and
+/// that no entry is in a directory which another entry names as a file.
+/// Existing files are never overwritten.
+pub fn extract(
+    entries: &[TapeEntry<'_>],
+    root: &Path,
+    epoch: Epoch,
+    preserve_special: bool,
+) -> Result<()> {
+    let paths = entries
+        .iter()
+        .map(|e| relative_path(&e.path))
+        .collect::<Result<Vec<_>>>()?;
+    check_conflicts(&paths)?;
+    for (entry, path) in entries.iter().zip(paths) {
+        let path = root.join(path);
+        extract_file(entry, &path, epoch, preserve_special)
+            .with_context(|| format!("extracting {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn extract_file(
+    entry: &TapeEntry<'_>,
+    path: &Path,
+    epoch: Epoch,
+    preserve_special: bool,
+) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(&entry.data)?;
+    if let Some(mtime) = entry.mtime {
+        let mtime = mtime.timestamp(epoch);
+        let since = Duration::new(mtime.as_second() as _, mtime.subsec_nanosecond() as _);
+        file.set_modified(SystemTime::UNIX_EPOCH + since)?;
+    }
+    if let Some(mode) = entry.mode {
+        file.set_permissions(Permissions::from_mode(host_mode(mode, preserve_special)))?;
+    }
+    Ok(())
+}
+
+/// The permission bits to give an extracted file, without the setuid, setgid
+/// and sticky bits unless they are preserved.
+fn host_mode(mode: u16, preserve_special: bool) -> u32 {
+    let mode = u32::from(mode);
+    if preserve_special {
+        mode & 0o7777
+    } else {
+        mode & 0o777
+    }
+}
+
+/// Checks that relative paths can all be extracted: none is repeated and none
+/// is the parent directory of another.
+fn check_conflicts(paths: &[PathBuf]) -> Result<()> {
+    let mut files = HashSet::new();
+    for path in paths {
+        if !files.insert(path.as_path()) {
+            bail!("duplicate path: {}", path.display());
+        }
+    }
+    for path in paths {
+        if let Some(parent) = path.ancestors().skip(1).find(|a| files.contains(a)) {
+            bail!(
+                "{} is in {}, which is a file",
+                path.display(),
+                parent.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Converts a path from a tape to one relative to the extraction root.
+///
+/// Leading slashes and `.` components are dropped. Paths with `..` components
+/// or without any file name are rejected, so that nothing can be written
+/// outside of the root. Names which are not UTF-8 are kept byte-for-byte.
+pub fn relative_path(path: &[u8]) -> Result<PathBuf> {
+    let mut rel = PathBuf::new();
+    for component in path.split(|&b| b == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => bail!("path escapes root: {:?}", Bytes(path)),
+            _ => rel.push(OsStr::from_bytes(component)),
+        }
+    }
+    if rel.as_os_str().is_empty() {
+        bail!("path has no file name: {:?}", Bytes(path));
+    }
+    Ok(rel)
+}
+
+#[test]
+fn relative_paths() {
+    assert_eq!(relative_path(b"/bin/ls").unwrap(), Path::new("bin/ls"));
+    assert_eq!(
+        relative_path(b"usr//./fort").unwrap(),
+        Path::new("usr/fort")
+    );
+    assert_eq!(
+        relative_path(b"/etc/\xFFx").unwrap().as_os_str().as_bytes(),
+        b"etc/\xFFx",
+    );
+    assert!(relative_path(b"/usr/../../etc/passwd").is_err());
+    assert!(relative_path(b"/./").is_err());
+}
+
+#[test]
+fn path_conflicts() {
+    let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+    assert!(check_conflicts(&paths(&["bin/ls", "bin/cat", "etc/init"])).is_ok());
+    assert!(check_conflicts(&paths(&["bin/ls", "etc/init", "bin/ls"])).is_err());
+    assert!(check_conflicts(&paths(&["usr/fort/fc1", "usr/fort"])).is_err());
+    assert!(check_conflicts(&paths(&["usr", "usr/fort/fc1"])).is_err());
+}
+
+#[test]
+fn special_bits() {
+    assert_eq!(host_mode(0o4755, false), 0o755);
+    assert_eq!(host_mode(0o2711, false), 0o711);
+    assert_eq!(host_mode(0o1777, false), 0o777);
+    assert_eq!(host_mode(0o644, false), 0o644);
+    assert_eq!(host_mode(0o4755, true), 0o4755);
+    assert_eq!(host_mode(0o106755, true), 0o6755);
+}
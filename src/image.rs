@@ -0,0 +1,271 @@
+//! Readers for tape images in emulator formats, which normalize them to the
+//! flat stream of 512-byte blocks expected by [`dir::Archive`] and
+//! [`Segmenter`].
+//!
+//! - [SIMH magtape](https://simh.trailing-edge.com/docs/simh_magtape.pdf)
+//!   images frame each record with its length before and after, and mark the
+//!   ends of files with a zero length.
+//! - SIMH DECtape images store the 256 words of each block in 16-bit words, as
+//!   for a PDP-11, which are already flat, or in 18-bit words padded to 32
+//!   bits. The block headers and checksums of the physical tape are not stored,
+//!   so the block number follows from the position.
+//!
+//! [`dir::Archive`]: crate::dir::Archive
+//! [`Segmenter`]: crate::segment::Segmenter
+
+#![warn(missing_docs)]
+
+use std::{borrow::Cow, fmt, ops::Range};
+
+use anyhow::{Result, bail};
+
+/// The format of a tape image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// A flat stream of blocks, like s1-bits and s2-bits, or a 16-bit DECtape
+    /// image.
+    Flat,
+    /// A SIMH magtape image.
+    SimhTape,
+    /// A SIMH DECtape image with 18-bit words.
+    DecTape18,
+}
+
+/// A SIMH magtape image read into a flat stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimhTape {
+    /// The contents of the records, concatenated.
+    pub data: Vec<u8>,
+    /// The records, in order.
+    pub records: Vec<Record>,
+}
+
+/// A record in a SIMH magtape image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The byte range of the record in the image, excluding framing.
+    pub image_range: Range<usize>,
+    /// The byte range of the record in the flat stream.
+    pub range: Range<usize>,
+    /// The index of the file on the tape, counting tape marks.
+    pub file: usize,
+    /// Whether the record was flagged as read with an error.
+    pub error: bool,
+}
+
+/// The number of bytes in a block of 256 18-bit words in a DECtape image.
+const DECTAPE18_BLOCK: usize = 256 * 4;
+
+const TAPE_MARK: u32 = 0;
+const ERASE_GAP: u32 = 0xFFFF_FFFE;
+const END_OF_MEDIUM: u32 = 0xFFFF_FFFF;
+const ERROR_FLAG: u32 = 0x8000_0000;
+const LEN_MASK: u32 = 0x0FFF_FFFF;
+
+/// The number of records whose framing is checked to detect a SIMH magtape
+/// image.
+const SIMH_PROBE_RECORDS: usize = 4;
+
+impl ImageFormat {
+    /// Detects the format of a tape image from its contents. Images which are
+    /// not recognized as another format are flat.
+    ///
+    /// An image is taken to be a SIMH magtape if the framing of its first few
+    /// records is intact, passing over tape marks and erase gaps, and it has
+    /// at least one record.
+    ///
+    /// An image is only taken to have 18-bit words if every word fits in 18
+    /// bits and some word is nonzero, since an image of all NULs is just as
+    /// well a flat tape.
+    pub fn detect(image: &[u8]) -> Self {
+        if is_simh_tape(image) {
+            ImageFormat::SimhTape
+        } else if image.len().is_multiple_of(DECTAPE18_BLOCK)
+            && words18(image).all(|w| w < 1 << 18)
+            && words18(image).any(|w| w != 0)
+        {
+            ImageFormat::DecTape18
+        } else {
+            ImageFormat::Flat
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Flat => "flat",
+            ImageFormat::SimhTape => "SIMH magtape",
+            ImageFormat::DecTape18 => "18-bit DECtape",
+        })
+    }
+}
+
+/// Normalizes a tape image in a detected format to a flat stream of blocks.
+pub fn normalize(image: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match ImageFormat::detect(image) {
+        ImageFormat::Flat => Ok(Cow::Borrowed(image)),
+        ImageFormat::SimhTape => Ok(Cow::Owned(read_simh_tape(image)?.data)),
+        ImageFormat::DecTape18 => Ok(Cow::Owned(read_dectape18(image)?)),
+    }
+}
+
+/// Reads the records of a SIMH magtape image, up to the end of the medium.
+pub fn read_simh_tape(image: &[u8]) -> Result<SimhTape> {
+    let mut tape = SimhTape {
+        data: Vec::new(),
+        records: Vec::new(),
+    };
+    let mut file = 0;
+    let mut offset = 0;
+    while offset < image.len() {
+        let Some(meta) = read_u32(image, offset) else {
+            bail!("truncated record length at offset {offset}");
+        };
+        match meta {
+            TAPE_MARK => file += 1,
+            ERASE_GAP => {}
+            END_OF_MEDIUM => break,
+            _ if meta & !(ERROR_FLAG | LEN_MASK) != 0 => {
+                bail!(
+                    "unsupported record class {:#x} at offset {offset}",
+                    meta >> 28
+                );
+            }
+            _ => {
+                let len = (meta & LEN_MASK) as usize;
+                let start = offset + 4;
+                let end = start + len;
+                let padded = end + len % 2;
+                if read_u32(image, padded) != Some(meta) {
+                    bail!("record at offset {offset} does not end with its length");
+                }
+                let flat = tape.data.len();
+                tape.data.extend_from_slice(&image[start..end]);
+                tape.records.push(Record {
+                    image_range: start..end,
+                    range: flat..flat + len,
+                    file,
+                    error: meta & ERROR_FLAG != 0,
+                });
+                offset = padded;
+            }
+        }
+        offset += 4;
+    }
+    Ok(tape)
+}
+
+/// Reads a SIMH DECtape image with 18-bit words, as written for PDP-11 tapes,
+/// keeping the low 16 bits of each word. Fails if any word uses the upper two
+/// bits, since the tape was then not written by a PDP-11.
+pub fn read_dectape18(image: &[u8]) -> Result<Vec<u8>> {
+    if !image.len().is_multiple_of(DECTAPE18_BLOCK) {
+        bail!("DECtape image is not a whole number of 256-word blocks");
+    }
+    let mut data = Vec::with_capacity(image.len() / 2);
+    for (i, word) in words18(image).enumerate() {
+        if word >= 1 << 16 {
+            bail!("word {i} has more than 16 bits: {word:#o}");
+        }
+        data.extend_from_slice(&(word as u16).to_le_bytes());
+    }
+    Ok(data)
+}
+
+fn is_simh_tape(image: &[u8]) -> bool {
+    let mut records = 0;
+    let mut offset = 0;
+    while records < SIMH_PROBE_RECORDS && offset < image.len() {
+        match read_u32(image, offset) {
+            Some(TAPE_MARK | ERASE_GAP) => {}
+            Some(END_OF_MEDIUM) => break,
+            Some(meta) if meta & !(ERROR_FLAG | LEN_MASK) == 0 => {
+                let len = (meta & LEN_MASK) as usize;
+                offset += 4 + len + len % 2;
+                if read_u32(image, offset) != Some(meta) {
+                    return false;
+                }
+                records += 1;
+            }
+            _ => return false,
+        }
+        offset += 4;
+    }
+    records > 0
+}
+
+fn words18(image: &[u8]) -> impl Iterator<Item = u32> {
+    image
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Option<u32> {
+    let bytes = image.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[test]
+fn simh_tape_s2() {
+    let s2 = std::fs::read("s2-bits").unwrap();
+    assert_eq!(ImageFormat::detect(&s2), ImageFormat::Flat);
+    assert_eq!(
+        ImageFormat::detect(&std::fs::read("s1-bits").unwrap()),
+        ImageFormat::Flat
+    );
+
+    let mut image = Vec::new();
+    for block in s2.chunks(512) {
+        let len = (block.len() as u32).to_le_bytes();
+        image.extend_from_slice(&len);
+        image.extend_from_slice(block);
+        image.extend_from_slice(&len);
+    }
+    image.extend_from_slice(&TAPE_MARK.to_le_bytes());
+    image.extend_from_slice(&END_OF_MEDIUM.to_le_bytes());
+    assert_eq!(ImageFormat::detect(&image), ImageFormat::SimhTape);
+    let tape = read_simh_tape(&image).unwrap();
+    assert_eq!(tape.data, s2);
+    assert_eq!(tape.records.len(), 578);
+    assert_eq!(tape.records[1].image_range, 524..1036);
+    assert_eq!(tape.records[1].range, 512..1024);
+
+    // A tape may start with a tape mark.
+    let mut marked = TAPE_MARK.to_le_bytes().to_vec();
+    marked.extend_from_slice(&image);
+    assert_eq!(ImageFormat::detect(&marked), ImageFormat::SimhTape);
+    assert_eq!(normalize(&marked).unwrap(), s2);
+
+    // Corrupt the trailing length of the first record.
+    image[516] ^= 1;
+    assert_eq!(ImageFormat::detect(&image), ImageFormat::Flat);
+    assert!(read_simh_tape(&image).is_err());
+
+    // Only the framing of the first few records is checked.
+    image[516] ^= 1;
+    image[520 * (SIMH_PROBE_RECORDS - 1) + 516] ^= 1;
+    assert_eq!(ImageFormat::detect(&image), ImageFormat::Flat);
+    image[520 * (SIMH_PROBE_RECORDS - 1) + 516] ^= 1;
+    image[520 * SIMH_PROBE_RECORDS + 516] ^= 1;
+    assert_eq!(ImageFormat::detect(&image), ImageFormat::SimhTape);
+}
+
+#[test]
+fn dectape18_s2() {
+    let s2 = std::fs::read("s2-bits").unwrap();
+    let image = s2
+        .chunks_exact(2)
+        .flat_map(|w| (u16::from_le_bytes(w.try_into().unwrap()) as u32).to_le_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(ImageFormat::detect(&image), ImageFormat::DecTape18);
+    assert_eq!(normalize(&image).unwrap(), s2);
+
+    let mut image = image;
+    image[2] = 1;
+    assert!(read_dectape18(&image).is_err());
+
+    // Blank tapes have no words to go by.
+    assert_eq!(ImageFormat::detect(&[]), ImageFormat::Flat);
+    assert_eq!(ImageFormat::detect(&[0; 4096]), ImageFormat::Flat);
+}
@@ -0,0 +1,348 @@
+//! Inference of names for unnamed segments, by matching their contents against
+//! known files, to propose rows for `s1-segments.csv`.
+//!
+//! References are indexed by their 8-byte substrings (grams). A segment is
+//! compared with the references which share grams with it, and matches exactly
+//! when the tape at its offset holds the reference byte for byte and the
+//! segment ends no later than the residue after it, so the length can be taken
+//! from the reference even when the segment was split in the wrong place. A
+//! reference which is only a prefix of the segment does not match exactly.
+//! Otherwise, it is scored by the Dice coefficient of the sets
+//! of grams, which tolerates edits between versions of a file.
+
+#![warn(missing_docs)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    archive::{EntryKind, TapeEntry},
+    segment::SegmentKind,
+};
+
+/// The size of tape blocks, which files are padded to with residue.
+const BLOCK: usize = 512;
+
+/// The length of the substrings which are compared.
+const GRAM: usize = 8;
+
+/// Grams shared by this many references are too common to distinguish files,
+/// like runs of NULs, and are not used for matching.
+const MAX_GRAM_REFS: usize = 64;
+
+/// Known files to match segments against.
+#[derive(Clone, Debug, Default)]
+pub struct References {
+    refs: Vec<Reference>,
+    /// The references containing each gram.
+    index: HashMap<u64, Vec<u32>>,
+}
+
+/// A known file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    /// The path which the file would have on the tape.
+    pub path: Vec<u8>,
+    /// Where the file was found, e.g., the tape or directory.
+    pub source: String,
+    /// The file contents.
+    pub data: Vec<u8>,
+    /// The number of distinct grams in the file.
+    grams: usize,
+}
+
+/// A match of a segment against a reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    /// The index of the reference.
+    pub reference: usize,
+    /// Whether the tape holds the reference exactly.
+    pub exact: bool,
+    /// The proposed length of the file.
+    pub len: usize,
+    /// The similarity, from 0 to 1, which is 1 for exact matches.
+    pub score: f64,
+}
+
+/// A proposed row for a segments CSV.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Candidate {
+    /// The path of the matched reference.
+    pub path: String,
+    /// The byte offset in the tape of the segment.
+    pub offset: usize,
+    /// The proposed length, which is `auto` for fuzzy matches.
+    pub length: String,
+    /// The similarity, from 0 to 1.
+    pub score: f64,
+    /// Where the reference was found, and its path there.
+    pub reference: String,
+}
+
+impl References {
+    /// Creates an empty set of references.
+    pub fn new() -> Self {
+        References::default()
+    }
+
+    /// The references, in the order they were added.
+    pub fn refs(&self) -> &[Reference] {
+        &self.refs
+    }
+
+    /// Adds a reference file.
+    pub fn add(&mut self, path: Vec<u8>, source: String, data: Vec<u8>) {
+        let id = self.refs.len() as u32;
+        let grams = grams(&data);
+        for &gram in &grams {
+            let refs = self.index.entry(gram).or_default();
+            if refs.len() < MAX_GRAM_REFS {
+                refs.push(id);
+            }
+        }
+        self.refs.push(Reference {
+            path,
+            source,
+            data,
+            grams: grams.len(),
+        });
+    }
+
+    /// Adds the files of a tape as references.
+    pub fn add_entries(&mut self, entries: &[TapeEntry<'_>], source: &str) {
+        for entry in entries {
+            self.add(entry.path.to_vec(), source.to_owned(), entry.data.to_vec());
+        }
+    }
+
+    /// Adds the files in a directory tree as references, with paths relative
+    /// to it made absolute. Files longer than `max_len` are skipped.
+    pub fn add_dir(&mut self, dir: &Path, max_len: usize) -> Result<()> {
+        self.add_dir_at(dir, dir, max_len)
+    }
+
+    fn add_dir_at(&mut self, root: &Path, dir: &Path, max_len: usize) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.add_dir_at(root, &path, max_len)?;
+            } else if file_type.is_file() && entry.metadata()?.len() <= max_len as u64 {
+                let mut name = b"/".to_vec();
+                name.extend_from_slice(path.strip_prefix(root)?.as_os_str().as_bytes());
+                let source = root.display().to_string();
+                self.add(name, source, fs::read(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Matches data at an offset in the tape against the references and
+    /// returns the best matches, best first.
+    pub fn matches(&self, tape: &[u8], offset: usize, len: usize, limit: usize) -> Vec<Match> {
+        let data = &tape[offset..offset + len];
+        let grams = grams(data);
+        let mut shared = HashMap::<u32, usize>::new();
+        for gram in &grams {
+            if let Some(refs) = self.index.get(gram)
+                && refs.len() < MAX_GRAM_REFS
+            {
+                for &id in refs {
+                    *shared.entry(id).or_default() += 1;
+                }
+            }
+        }
+        let mut matches = shared
+            .into_iter()
+            .map(|(id, shared)| {
+                let reference = &self.refs[id as usize];
+                let end = offset + reference.data.len();
+                if tape[offset..].starts_with(&reference.data)
+                    && offset + len <= end.next_multiple_of(BLOCK)
+                {
+                    Match {
+                        reference: id as usize,
+                        exact: true,
+                        len: reference.data.len(),
+                        score: 1.0,
+                    }
+                } else {
+                    Match {
+                        reference: id as usize,
+                        exact: false,
+                        len,
+                        score: 2.0 * shared as f64 / (grams.len() + reference.grams) as f64,
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.len.cmp(&a.len))
+                .then(a.reference.cmp(&b.reference))
+        });
+        matches.truncate(limit);
+        matches
+    }
+}
+
+impl Reference {
+    /// Describes where the reference was found.
+    pub fn describe(&self) -> String {
+        format!("{}:{}", self.source, String::from_utf8_lossy(&self.path))
+    }
+}
+
+/// Proposes names for the unnamed original segments of a tape, from their best
+/// match with a score of at least `min_score`.
+pub fn infer(
+    tape: &[u8],
+    entries: &[TapeEntry<'_>],
+    refs: &References,
+    min_score: f64,
+) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    for entry in entries {
+        let EntryKind::Segment(segment, None) = entry.kind else {
+            continue;
+        };
+        if segment.kind != SegmentKind::Original {
+            continue;
+        }
+        let Some(m) = refs
+            .matches(tape, entry.offset, entry.data.len(), 1)
+            .into_iter()
+            .next()
+        else {
+            continue;
+        };
+        if m.score < min_score {
+            continue;
+        }
+        let reference = &refs.refs[m.reference];
+        candidates.push(Candidate {
+            path: String::from_utf8_lossy(&reference.path).into_owned(),
+            offset: entry.offset,
+            length: if m.exact {
+                m.len.to_string()
+            } else {
+                "auto".to_owned()
+            },
+            score: (m.score * 1000.0).round() / 1000.0,
+            reference: reference.describe(),
+        });
+    }
+    candidates
+}
+
+/// Writes candidates as CSV rows with a header.
+pub fn write_csv<W: Write>(candidates: &[Candidate], w: W) -> Result<()> {
+    let mut w = csv::Writer::from_writer(w);
+    for candidate in candidates {
+        w.serialize(candidate)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// The distinct grams in the data.
+fn grams(data: &[u8]) -> HashSet<u64> {
+    data.windows(GRAM)
+        .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn infer_s1() {
+    use crate::{annotation::AnnotationFile, archive::TapeArchive, segment::Segmenter};
+
+    let s1 = std::fs::read("s1-bits").unwrap();
+    let s2 = std::fs::read("s2-bits").unwrap();
+    let mut refs = References::new();
+    refs.add_entries(&crate::archive::open_dir(&s2).unwrap().entries(), "s2");
+
+    let mut segmenter = Segmenter::new(&s1, 512);
+    segmenter.segment_blocks();
+    let entries = segmenter.entries();
+    let candidates = infer(&s1, &entries, &refs, 0.5);
+    assert!(candidates.iter().all(|c| c.score == 1.0));
+    let found = candidates
+        .iter()
+        .map(|c| (&*c.path, c.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ("/etc/init", 33792),
+            ("/etc/getty", 34816),
+            ("/bin/chmod", 35840),
+            ("/bin/date", 36864),
+            ("/bin/login", 38400),
+            ("/bin/mkdir", 40448),
+            ("/bin/sh", 41472),
+            ("/bin/tap", 43008),
+            ("/bin/ls", 47616),
+        ],
+    );
+    let chmod = &candidates[2];
+    assert_eq!(chmod.length, "82");
+    assert_eq!(chmod.reference, "s2:/bin/chmod");
+
+    // Segments which were already named match the files they were named for,
+    // and are not proposed again.
+    let annotations = AnnotationFile::open("s1-segments.csv".as_ref()).unwrap();
+    let mut segmenter = Segmenter::new(&s1, 512);
+    for header in annotations.headers() {
+        segmenter.add_header(header.clone()).unwrap();
+    }
+    segmenter.segment_blocks();
+    let entries = segmenter.entries();
+    let mut named = Vec::new();
+    for entry in &entries {
+        if matches!(entry.kind, EntryKind::Segment(_, Some(_)))
+            && refs.refs().iter().any(|r| r.path == *entry.path)
+        {
+            let m = refs.matches(&s1, entry.offset, entry.data.len(), 1)[0];
+            assert_eq!(refs.refs()[m.reference].path, *entry.path);
+            assert!(m.exact);
+            assert_eq!(m.len, entry.data.len());
+            named.push(entry.offset);
+        }
+    }
+    assert_eq!(named.len(), 8);
+    let candidates = infer(&s1, &entries, &refs, 0.5);
+    assert!(candidates.iter().all(|c| !named.contains(&c.offset)));
+
+    // A reference which is only the start of a segment does not match it
+    // exactly.
+    let sh = entries.iter().find(|e| e.offset == 41472).unwrap();
+    assert!(sh.data.len() > BLOCK);
+    let mut short = References::new();
+    short.add(
+        b"/bin/sh".to_vec(),
+        "short".to_owned(),
+        sh.data[..200].to_vec(),
+    );
+    let m = short.matches(&s1, sh.offset, sh.data.len(), 1)[0];
+    assert!(!m.exact && m.score < 1.0);
+    assert_eq!(m.len, sh.data.len());
+    let m = short.matches(&s1, sh.offset, 200, 1)[0];
+    assert!(m.exact && m.len == 200);
+
+    // The segment at 31232 only weakly resembles /bin/tap.
+    let tap = refs.matches(&s1, 31232, 1474, 1)[0];
+    assert_eq!(refs.refs()[tap.reference].path, b"/bin/tap");
+    assert!(!tap.exact && tap.score < 0.5);
+}
@@ -128,6 +128,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::single_range_in_vec_init)]
     fn get_disjoint() {
         let set = IntervalSet {
             intervals: vec![0..0, 1..3, 7..10, 20..M],
@@ -1,6 +1,24 @@
+pub mod annotation;
+pub mod archive;
+pub mod coverage;
 pub mod detect;
+pub mod diff;
+pub mod dir;
+pub mod epoch;
+pub mod export;
+pub mod extract;
+pub mod image;
+pub mod infer;
 pub mod interval;
+pub mod list;
+pub mod map;
+pub mod passwd;
+pub mod pax;
+pub mod provenance;
+pub mod recover;
 pub mod segment;
 pub mod split;
 pub mod tap;
+pub mod timeline;
+pub mod tp;
 pub mod util;
@@ -0,0 +1,119 @@
+//! Listings of the files in a tape, in the style of V1 `tap t` and `ls -l`.
+
+#![warn(missing_docs)]
+
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    archive::{EntryKind, Mtime, TapeEntry},
+    passwd::{User, UserNames},
+    tap::Epoch,
+    util::Bytes,
+};
+
+/// The order of a listing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// The order of the tape format, i.e., directory order.
+    #[default]
+    Tape,
+    /// By path.
+    Path,
+    /// By the block which the contents start at.
+    Block,
+    /// By modification time, with unknown times first.
+    Mtime,
+}
+
+/// Sorts the entries for a listing. The sort is stable, so ties remain in tape
+/// order.
+pub fn sort_entries(entries: &mut [TapeEntry<'_>], key: SortKey) {
+    match key {
+        SortKey::Tape => {}
+        SortKey::Path => entries.sort_by(|a, b| a.path.cmp(&b.path)),
+        SortKey::Block => entries.sort_by_key(|e| e.offset),
+        // Times within a tape are in the same epoch, so any will do.
+        SortKey::Mtime => entries.sort_by_key(|e| e.mtime.map(|t| t.timestamp(Epoch::Y1972))),
+    }
+}
+
+/// Writes one line for each entry with its mode, owner, size, block,
+/// modification time and path, e.g.:
+///
+/// ```text
+/// -xrwr- bin         82    25 1972-01-17 17:53:35:26 "/bin/chmod"
+/// ```
+///
+/// V1 times are formatted in the given epoch with a 1/60 second field, like
+/// [`Time`](crate::tap::Time). Unknown fields are shown as `?`.
+pub fn write_listing<W: Write>(
+    entries: &[TapeEntry<'_>],
+    epoch: Epoch,
+    names: &dyn UserNames,
+    mut w: W,
+) -> io::Result<()> {
+    for entry in entries {
+        let mode = match entry.kind {
+            EntryKind::Tap(h) => h.mode().to_string(),
+            EntryKind::Tp(h) => h.mode().to_string(),
+            EntryKind::Segment(..) => match entry.mode {
+                Some(mode) => format!("{mode:04o}"),
+                None => "?".to_owned(),
+            },
+        };
+        let uid = match entry.uid {
+            Some(uid) => User(uid, names).to_string(),
+            None => "?".to_owned(),
+        };
+        let mtime = match entry.mtime {
+            Some(Mtime::V1(time)) => format!("{time:.*}", epoch.year() as usize),
+            Some(Mtime::Unix(t)) => t.strftime("%F %T").to_string(),
+            None => "?".to_owned(),
+        };
+        writeln!(
+            w,
+            "{mode:6} {uid:8} {:5} {:5} {mtime:22} {:?}",
+            entry.data.len(),
+            entry.offset / 512,
+            Bytes(&entry.path),
+        )?;
+    }
+    Ok(())
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tape" => Ok(SortKey::Tape),
+            "path" => Ok(SortKey::Path),
+            "block" => Ok(SortKey::Block),
+            "mtime" => Ok(SortKey::Mtime),
+            _ => bail!("invalid sort key: {s:?}"),
+        }
+    }
+}
+
+#[test]
+fn list_s2() {
+    use crate::{archive::open_dir, passwd::Passwd};
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = open_dir(&tape).unwrap();
+    let mut entries = archive.entries();
+    let passwd = Passwd::from_entries(&entries).unwrap();
+    sort_entries(&mut entries, SortKey::Mtime);
+    let mut out = Vec::new();
+    write_listing(&entries[..2], Epoch::Y1972, &passwd, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "--rwrw root     16448   538 1972-01-01 00:33:51:52 \"/core\"\n\
+         --rwrw jack        54   529 1972-01-01 00:52:29:04 \"/usr/x\"\n",
+    );
+}
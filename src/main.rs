@@ -1,19 +1,100 @@
 use std::{
     borrow::Cow,
-    ffi::OsStr,
+    env,
+    ffi::{OsStr, OsString},
     fs::{self, File},
-    os::unix::ffi::OsStrExt,
+    io,
     path::Path,
+    process,
+    str::FromStr,
 };
 
+use anyhow::{Result, bail};
+use jiff::{SignedDuration, Timestamp, civil::Date, tz::TimeZone};
+
 use unix_1972_tapes::{
+    annotation::AnnotationFile,
+    archive::{EntryKind, Mtime, TapeArchive, open_dir, v1_times},
+    coverage::{Category, Coverage},
     detect::{Magic, is_text},
-    segment::{SegmentHeader, SegmentKind, SegmentLen, Segmenter},
-    tap::Header,
+    diff::{self, Side},
+    dir::Format,
+    epoch::infer_epoch,
+    export::{write_csv, write_epoch_tars, write_json, write_tar},
+    extract::extract,
+    image::{ImageFormat, normalize},
+    infer::{self, References},
+    list::{SortKey, sort_entries, write_listing},
+    map,
+    passwd::Passwd,
+    provenance, recover,
+    segment::{Evidence, SegmentHeader, SegmentKind, SegmentLen, Segmenter},
+    tap::{Archive, Editor, Epoch, Mode, Time},
+    timeline::{self, Period, timeline},
+    tp,
     util::{BlockLen, Bytes},
 };
 
+const USAGE: &str = "usage: unix-1972-tapes [COMMAND]
+
+With no command, segments s1-bits and s2-bits and writes them to tar files.
+Tapes may be flat, SIMH magtape or SIMH DECtape images.
+
+Commands:
+  coverage TAPE [SEGMENTS_CSV]
+  diff TAPE [--old SEGMENTS_CSV|--old-listing LISTING]
+       [--new SEGMENTS_CSV|--new-listing LISTING] [--format text|json]
+  edit TAPE OUT PATH [--path PATH] [--mode MODE] [--uid UID] [--size SIZE]
+       [--mtime TICKS] [--block BLOCK]
+  extract TAPE DIR [--epoch YEAR] [--archive-date DATE] [--preserve-special]
+  infer TAPE [SEGMENTS_CSV] [--tap TAPE]... [--ref DIR]... [--min-score SCORE]
+  list TAPE [--epoch YEAR] [--archive-date DATE] [--sort tape|path|block|mtime]
+  map TAPE OUT.svg|OUT.html [SEGMENTS_CSV]
+  provenance TAPE [SEGMENTS_CSV]
+  recover TAPE
+  segments TAPE [SEGMENTS_CSV] [--epoch YEAR] [--archive-date DATE]
+           [--format csv|json]
+  tar TAPE OUT [--epoch YEAR|raw|all] [--archive-date DATE]
+  timeline TAPE [--epoch YEAR] [--archive-date DATE] [--by day|week]
+           [--batch-gap SECONDS] [--format csv|json]
+
+Without --epoch, the epoch of V1 times is inferred, ruling out epochs which
+put files after the --archive-date, given as YYYY-MM-DD or an RFC 3339 time.
+
+The listing written by segments can be given to diff as a baseline for
+comparing segmentations across builds.";
+
 fn main() {
+    let args = env::args_os().skip(1).collect::<Vec<_>>();
+    let res = match args.first().map(|arg| arg.to_str()) {
+        None => {
+            dump_tapes();
+            Ok(())
+        }
+        Some(Some("coverage")) => coverage_tape(&args[1..]),
+        Some(Some("diff")) => diff_tape(&args[1..]),
+        Some(Some("edit")) => edit_tape(&args[1..]),
+        Some(Some("extract")) => extract_tape(&args[1..]),
+        Some(Some("infer")) => infer_tape(&args[1..]),
+        Some(Some("list")) => list_tape(&args[1..]),
+        Some(Some("map")) => map_tape(&args[1..]),
+        Some(Some("provenance")) => provenance_tape(&args[1..]),
+        Some(Some("recover")) => recover_tape(&args[1..]),
+        Some(Some("segments")) => segments_tape(&args[1..]),
+        Some(Some("tar")) => tar_tape(&args[1..]),
+        Some(Some("timeline")) => timeline_tape(&args[1..]),
+        Some(_) => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(err) = res {
+        eprintln!("error: {err:#}");
+        process::exit(1);
+    }
+}
+
+fn dump_tapes() {
     let s1 = fs::read("s1-bits").unwrap();
     segment_tape(
         &s1,
@@ -24,106 +105,562 @@ fn main() {
 
     let s2 = fs::read("s2-bits").unwrap();
     segment_tape(&s2, None, Path::new("s2-segments.tar"), false);
-    let mut tar = tar::Builder::new(File::create("s2-files.tar").unwrap());
-    for chunk in s2.chunks_exact(64) {
-        if let Some(file) = Header::parse(chunk.try_into().unwrap()) {
-            let data = &s2[file.range()];
-            tar.append(&file.to_tar_header(), data).unwrap();
+    let archive = open_dir(&s2).unwrap();
+    for diagnostic in archive.diagnostics() {
+        eprintln!("{diagnostic}");
+    }
+    let entries = archive.entries();
+    let epoch = infer_epoch(v1_times(&entries), None);
+    write_tar(&entries, Some(epoch), File::create("s2-files.tar").unwrap()).unwrap();
+}
+
+fn coverage_tape(args: &[OsString]) -> Result<()> {
+    let (tape_path, csv_path) = match args {
+        [tape_path] => (tape_path, None),
+        [tape_path, csv_path] => (tape_path, Some(Path::new(csv_path))),
+        _ => bail!("{USAGE}"),
+    };
+    let tape = read_tape(tape_path)?;
+    let segmenter = open_segmenter(&tape, csv_path)?;
+    // Tapes with a directory have a boot block followed by the directory.
+    // Otherwise, like s1, the first two blocks are the boot code.
+    let dir = match Format::detect(&tape) {
+        Some(Format::Tap) => Some(Archive::parse(&tape)?.dir_range()),
+        Some(Format::Tp) => Some(tp::Archive::parse(&tape)?.dir_range()),
+        None => None,
+    };
+    let regions = match dir {
+        Some(dir) => vec![(0..dir.start, Category::Boot), (dir, Category::Directory)],
+        None => vec![(0..1024, Category::Boot)],
+    };
+    let coverage = Coverage::new(&segmenter, &regions)?;
+    coverage.write_report(io::stdout().lock())?;
+    Ok(())
+}
+
+fn diff_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(
+        args,
+        &[
+            "--old",
+            "--old-listing",
+            "--new",
+            "--new-listing",
+            "--format",
+        ],
+        &[],
+    )?;
+    let [tape_path] = args[..] else {
+        bail!("{USAGE}");
+    };
+    let tape = read_tape(tape_path)?;
+    let old = diff_side(&tape, &opts, "--old", "--old-listing")?;
+    let new = diff_side(&tape, &opts, "--new", "--new-listing")?;
+    let changes = diff::diff(&old, &new);
+    match opts.get("--format").map(|f| f.to_str()) {
+        None | Some(Some("text")) => diff::write_text(&changes, io::stdout().lock())?,
+        Some(Some("json")) => diff::write_json(&changes, io::stdout().lock())?,
+        Some(_) => bail!("invalid format: expected text or json"),
+    }
+    Ok(())
+}
+
+/// Reads one side of a diff from a saved listing, or segments the tape with an
+/// optional CSV of segments.
+fn diff_side(
+    tape: &[u8],
+    opts: &Opts<'_>,
+    csv_flag: &str,
+    listing_flag: &str,
+) -> Result<Vec<Side>> {
+    match (opts.get(csv_flag), opts.get(listing_flag)) {
+        (Some(_), Some(_)) => bail!("{csv_flag} and {listing_flag} cannot both be given"),
+        (None, Some(listing)) => diff::read_listing(&fs::read_to_string(listing)?),
+        (csv_path, None) => {
+            let segmenter = open_segmenter(tape, csv_path.map(Path::new))?;
+            Ok(diff::sides(&segmenter.entries()))
         }
     }
 }
 
-fn segment_tape(tape: &[u8], csv_path: Option<&Path>, tar_path: &Path, include_residue: bool) {
+fn edit_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(
+        args,
+        &["--path", "--mode", "--uid", "--size", "--mtime", "--block"],
+        &[],
+    )?;
+    let [tape_path, out_path, path] = args[..] else {
+        bail!("{USAGE}");
+    };
+    let mut tape = fs::read(tape_path)?;
+    let mut editor = Editor::new(&mut tape)?;
+    let header = editor.find_mut(path.as_encoded_bytes())?;
+    if let Some(path) = opts.get("--path") {
+        header.set_path(path.as_encoded_bytes())?;
+    }
+    if let Some(mode) = opts.get("--mode") {
+        header.set_mode(parse_value::<Mode>("--mode", mode)?);
+    }
+    if let Some(uid) = opts.get("--uid") {
+        header.set_uid(parse_value("--uid", uid)?);
+    }
+    if let Some(size) = opts.get("--size") {
+        header.set_size(parse_value("--size", size)?);
+    }
+    if let Some(mtime) = opts.get("--mtime") {
+        header.set_mtime(Time(parse_value("--mtime", mtime)?));
+    }
+    if let Some(block) = opts.get("--block") {
+        header.set_block(parse_value("--block", block)?);
+    }
+    fs::write(out_path, &tape)?;
+    Ok(())
+}
+
+fn extract_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(
+        args,
+        &["--epoch", "--archive-date"],
+        &["--preserve-special"],
+    )?;
+    let [tape_path, dir] = args[..] else {
+        bail!("{USAGE}");
+    };
+    let tape = read_tape(tape_path)?;
+    let archive = open_dir(&tape)?;
+    for diagnostic in archive.diagnostics() {
+        eprintln!("{diagnostic}");
+    }
+    let entries = archive.entries();
+    let epoch = match opts.get("--epoch") {
+        Some(year) => parse_epoch(year)?,
+        None => infer_epoch(v1_times(&entries), parse_archive_date(&opts)?),
+    };
+    extract(
+        &entries,
+        Path::new(dir),
+        epoch,
+        opts.has("--preserve-special"),
+    )
+}
+
+fn infer_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(args, &["--tap", "--ref", "--min-score"], &[])?;
+    let (tape_path, csv_path) = match args[..] {
+        [tape_path] => (tape_path, None),
+        [tape_path, csv_path] => (tape_path, Some(Path::new(csv_path))),
+        _ => bail!("{USAGE}"),
+    };
+    let tape = read_tape(tape_path)?;
+    let segmenter = open_segmenter(&tape, csv_path)?;
+    let mut refs = References::new();
+    for tap_path in opts.get_all("--tap") {
+        let tap = read_tape(tap_path)?;
+        let archive = open_dir(&tap)?;
+        refs.add_entries(&archive.entries(), &tap_path.to_string_lossy());
+    }
+    for dir in opts.get_all("--ref") {
+        refs.add_dir(Path::new(dir), tape.len())?;
+    }
+    let min_score = match opts.get("--min-score") {
+        Some(score) => parse_value("--min-score", score)?,
+        None => 0.5,
+    };
+    let candidates = infer::infer(&tape, &segmenter.entries(), &refs, min_score);
+    infer::write_csv(&candidates, io::stdout().lock())
+}
+
+fn list_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(args, &["--epoch", "--archive-date", "--sort"], &[])?;
+    let [tape_path] = args[..] else {
+        bail!("{USAGE}");
+    };
+    let tape = read_tape(tape_path)?;
+    let archive = open_dir(&tape)?;
+    for diagnostic in archive.diagnostics() {
+        eprintln!("{diagnostic}");
+    }
+    let mut entries = archive.entries();
+    let epoch = match opts.get("--epoch") {
+        Some(year) => parse_epoch(year)?,
+        None => infer_epoch(v1_times(&entries), parse_archive_date(&opts)?),
+    };
+    let sort = match opts.get("--sort") {
+        Some(key) => match key.to_str() {
+            Some(key) => key.parse()?,
+            None => bail!("invalid sort key: {}", key.display()),
+        },
+        None => SortKey::Tape,
+    };
+    let passwd = Passwd::from_entries(&entries).unwrap_or_default();
+    sort_entries(&mut entries, sort);
+    write_listing(&entries, epoch, &passwd, io::stdout().lock())?;
+    Ok(())
+}
+
+fn recover_tape(args: &[OsString]) -> Result<()> {
+    let [tape_path] = args else {
+        bail!("{USAGE}");
+    };
+    let tape = read_tape(tape_path)?;
+    for candidate in recover::scan(&tape) {
+        println!("{candidate}");
+    }
+    Ok(())
+}
+
+fn map_tape(args: &[OsString]) -> Result<()> {
+    let (tape_path, out_path, csv_path) = match args {
+        [tape_path, out_path] => (tape_path, Path::new(out_path), None),
+        [tape_path, out_path, csv_path] => {
+            (tape_path, Path::new(out_path), Some(Path::new(csv_path)))
+        }
+        _ => bail!("{USAGE}"),
+    };
+    let tape = read_tape(tape_path)?;
+    let segmenter = open_segmenter(&tape, csv_path)?;
+    let entries = segmenter.entries();
+    match out_path.extension().and_then(|ext| ext.to_str()) {
+        Some("svg") => map::write_svg(&entries, tape.len(), File::create(out_path)?)?,
+        Some("html" | "htm") => {
+            let title = tape_path.to_string_lossy();
+            map::write_html(&entries, tape.len(), &title, File::create(out_path)?)?
+        }
+        _ => bail!("map must be written to a .svg or .html file"),
+    }
+    Ok(())
+}
+
+fn provenance_tape(args: &[OsString]) -> Result<()> {
+    let (tape_path, csv_path) = match args {
+        [tape_path] => (tape_path, None),
+        [tape_path, csv_path] => (tape_path, Some(Path::new(csv_path))),
+        _ => bail!("{USAGE}"),
+    };
+    let tape = read_tape(tape_path)?;
+    let segmenter = open_segmenter(&tape, csv_path)?;
+    let entries = segmenter.entries();
+    for p in provenance::trace(&tape, segmenter.segments(), 512) {
+        let residue = &entries[p.residue];
+        println!(
+            "{} {:?}{}",
+            residue.offset,
+            Bytes(&residue.path),
+            if p.is_proven() { "" } else { " (unproven)" },
+        );
+        for source in &p.sources {
+            print!("  {:?} ", source.range);
+            match &source.origin {
+                Some(origin) => {
+                    let from = &entries[origin.segment];
+                    println!(
+                        "{} {} {:?} at {}",
+                        if origin.matches {
+                            "from"
+                        } else {
+                            "differs from"
+                        },
+                        origin.offset,
+                        Bytes(&from.path),
+                        origin.offset - from.offset,
+                    );
+                }
+                None => println!("from no earlier block"),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn segments_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(args, &["--epoch", "--archive-date", "--format"], &[])?;
+    let (tape_path, csv_path) = match args[..] {
+        [tape_path] => (tape_path, None),
+        [tape_path, csv_path] => (tape_path, Some(Path::new(csv_path))),
+        _ => bail!("{USAGE}"),
+    };
+    let tape = read_tape(tape_path)?;
+    let segmenter = open_segmenter(&tape, csv_path)?;
+    let entries = segmenter.entries();
+    let epoch = match opts.get("--epoch") {
+        Some(year) => parse_epoch(year)?,
+        None => infer_epoch(v1_times(&entries), parse_archive_date(&opts)?),
+    };
+    match opts.get("--format").map(|f| f.to_str()) {
+        None | Some(Some("csv")) => write_csv(&entries, epoch, io::stdout().lock()),
+        Some(Some("json")) => write_json(&entries, epoch, io::stdout().lock()),
+        Some(_) => bail!("invalid format: expected csv or json"),
+    }
+}
+
+fn tar_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(args, &["--epoch", "--archive-date"], &[])?;
+    let [tape_path, out_path] = args[..] else {
+        bail!("{USAGE}");
+    };
+    let tape = read_tape(tape_path)?;
+    let archive = open_dir(&tape)?;
+    for diagnostic in archive.diagnostics() {
+        eprintln!("{diagnostic}");
+    }
+    let entries = archive.entries();
+    let epoch = match opts.get("--epoch") {
+        Some(epoch) if *epoch == "all" => {
+            for path in write_epoch_tars(&entries, Path::new(out_path))? {
+                println!("{}", path.display());
+            }
+            return Ok(());
+        }
+        Some(epoch) if *epoch == "raw" => None,
+        Some(year) => Some(parse_epoch(year)?),
+        None => Some(infer_epoch(v1_times(&entries), parse_archive_date(&opts)?)),
+    };
+    write_tar(&entries, epoch, File::create(out_path)?)?;
+    Ok(())
+}
+
+fn timeline_tape(args: &[OsString]) -> Result<()> {
+    let (args, opts) = parse_opts(
+        args,
+        &[
+            "--epoch",
+            "--archive-date",
+            "--by",
+            "--batch-gap",
+            "--format",
+        ],
+        &[],
+    )?;
+    let [tape_path] = args[..] else {
+        bail!("{USAGE}");
+    };
+    let tape = read_tape(tape_path)?;
+    let archive = open_dir(&tape)?;
+    for diagnostic in archive.diagnostics() {
+        eprintln!("{diagnostic}");
+    }
+    let entries = archive.entries();
+    let epoch = match opts.get("--epoch") {
+        Some(year) => parse_epoch(year)?,
+        None => infer_epoch(v1_times(&entries), parse_archive_date(&opts)?),
+    };
+    let period = match opts.get("--by") {
+        Some(period) => parse_value::<Period>("--by", period)?,
+        None => Period::Day,
+    };
+    let batch_gap = match opts.get("--batch-gap") {
+        Some(gap) => match SignedDuration::try_from_secs_f64(parse_value("--batch-gap", gap)?) {
+            Ok(gap) => gap,
+            Err(_) => bail!("invalid value for --batch-gap: {}", gap.display()),
+        },
+        None => SignedDuration::ZERO,
+    };
+    let events = timeline(&entries, epoch, period, batch_gap);
+    match opts.get("--format").map(|f| f.to_str()) {
+        None | Some(Some("csv")) => timeline::write_csv(&events, io::stdout().lock())?,
+        Some(Some("json")) => timeline::write_json(&events, io::stdout().lock())?,
+        Some(_) => bail!("invalid format: expected csv or json"),
+    }
+    Ok(())
+}
+
+/// Reads a tape image and normalizes it to a flat stream of blocks.
+fn read_tape(path: &OsStr) -> Result<Vec<u8>> {
+    let image = fs::read(path)?;
+    let format = ImageFormat::detect(&image);
+    if format != ImageFormat::Flat {
+        eprintln!("reading {} as a {format} image", path.display());
+    }
+    Ok(normalize(&image)?.into_owned())
+}
+
+/// Options with values, in the order they were given.
+struct Opts<'a>(Vec<(&'static str, &'a OsStr)>);
+
+impl<'a> Opts<'a> {
+    /// The last value of an option.
+    fn get(&self, flag: &str) -> Option<&&'a OsStr> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(f, _)| *f == flag)
+            .map(|(_, v)| v)
+    }
+
+    /// Whether a switch was given.
+    fn has(&self, flag: &str) -> bool {
+        self.0.iter().any(|(f, _)| *f == flag)
+    }
+
+    /// All values of an option which may be repeated.
+    fn get_all(&self, flag: &str) -> impl Iterator<Item = &'a OsStr> {
+        self.0
+            .iter()
+            .filter(move |(f, _)| *f == flag)
+            .map(|&(_, v)| v)
+    }
+}
+
+/// Splits arguments into positional arguments, options with values and
+/// switches without.
+fn parse_opts<'a>(
+    args: &'a [OsString],
+    flags: &[&'static str],
+    switches: &[&'static str],
+) -> Result<(Vec<&'a OsStr>, Opts<'a>)> {
+    let mut positional = Vec::new();
+    let mut opts = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(&flag) = flags.iter().find(|&&flag| arg == flag) {
+            let Some(value) = args.next() else {
+                bail!("missing value for {flag}");
+            };
+            opts.push((flag, &**value));
+        } else if let Some(&switch) = switches.iter().find(|&&switch| arg == switch) {
+            opts.push((switch, OsStr::new("")));
+        } else if arg.as_encoded_bytes().starts_with(b"--") {
+            bail!("unknown option: {}", arg.display());
+        } else {
+            positional.push(&**arg);
+        }
+    }
+    Ok((positional, Opts(opts)))
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &OsStr) -> Result<T> {
+    match value.to_str().and_then(|value| value.parse().ok()) {
+        Some(value) => Ok(value),
+        None => bail!("invalid value for {flag}: {}", value.display()),
+    }
+}
+
+fn parse_epoch(year: &OsStr) -> Result<Epoch> {
+    match year.to_str().and_then(|year| year.parse().ok()) {
+        Some(year) => match Epoch::from_year(year) {
+            Some(epoch) => Ok(epoch),
+            None => bail!("unsupported epoch: {year}"),
+        },
+        None => bail!("invalid epoch: {}", year.display()),
+    }
+}
+
+/// Parses the date an archive was written, for inferring its epoch.
+fn parse_archive_date(opts: &Opts<'_>) -> Result<Option<Timestamp>> {
+    let Some(date) = opts.get("--archive-date") else {
+        return Ok(None);
+    };
+    if let Some(date) = date.to_str() {
+        if let Ok(timestamp) = date.parse::<Timestamp>() {
+            return Ok(Some(timestamp));
+        }
+        if let Ok(date) = date.parse::<Date>()
+            && let Ok(zoned) = date.to_zoned(TimeZone::UTC)
+        {
+            return Ok(Some(zoned.timestamp()));
+        }
+    }
+    bail!("invalid value for --archive-date: {}", date.display())
+}
+
+/// Segments a tape, using the headers in its tap directory, if it has one, and
+/// in a CSV of segments, if given.
+fn open_segmenter<'a>(tape: &'a [u8], csv_path: Option<&Path>) -> Result<Segmenter<'a>> {
     let mut segmenter = Segmenter::new(tape, 512);
 
-    for chunk in tape.chunks_exact(64) {
-        if let Some(h) = Header::parse(chunk.try_into().unwrap()) {
+    if let Ok(archive) = Archive::parse(tape) {
+        for h in archive.headers() {
             let file = SegmentHeader {
-                path: h.path().into(),
-                offset: h.offset(),
-                len: SegmentLen::Manual(h.size() as _),
+                mode: Some(h.mode().to_posix()),
+                uid: Some(h.uid as _),
+                mtime: Some(Mtime::V1(h.mtime())),
+                ..SegmentHeader::new(
+                    h.path().into(),
+                    h.offset(),
+                    SegmentLen::Manual(h.size() as _),
+                )
             };
-            segmenter.add_header(file).unwrap();
+            segmenter.add_header(file)?;
         }
     }
 
     if let Some(csv_path) = csv_path {
-        let mut csv = csv::ReaderBuilder::new()
-            .comment(Some(b'#'))
-            .from_path(csv_path)
-            .unwrap();
-        for header in csv.deserialize() {
-            segmenter.add_header(header.unwrap()).unwrap();
+        for header in AnnotationFile::open(csv_path)?.headers() {
+            segmenter.add_header(header.clone())?;
         }
     }
 
     segmenter.segment_blocks();
+    Ok(segmenter)
+}
 
-    let mut tar = tar::Builder::new(File::create(tar_path).unwrap());
-    let mut i = 0;
-    while i < segmenter.segments().len() {
-        let segment = &segmenter.segments()[i];
-        let mut h = tar::Header::new_old();
-        if let Some(file) = segmenter.header_for_offset(segment.offset) {
-            if let SegmentLen::Manual(len) = file.len
-                && len != segment.data.len()
-            {
-                eprintln!(
-                    "segment {:?} at offset {} has length {}; expected {}",
-                    Bytes(&file.path),
-                    segment.offset,
-                    BlockLen(segment.data.len()),
-                    BlockLen(len),
-                );
-            }
-            let path = file.path.strip_prefix(b"/").unwrap_or(&file.path);
-            h.set_path(OsStr::from_bytes(path)).unwrap();
-        } else {
-            let ext = if is_text(segment.data) { "txt" } else { "bin" };
-            let kind = match segment.kind {
-                SegmentKind::Original => "",
-                SegmentKind::Residue => ".copy",
-                SegmentKind::AllNul => ".nul",
-                SegmentKind::AllFF => ".ff",
-            };
-            let path = format!("segments/{}{kind}.{ext}", segment.offset);
-            h.set_path(path).unwrap();
+fn segment_tape(tape: &[u8], csv_path: Option<&Path>, tar_path: &Path, include_residue: bool) {
+    let segmenter = open_segmenter(tape, csv_path).unwrap();
+
+    let mut entries = segmenter.entries().into_iter().peekable();
+    let mut files = Vec::new();
+    while let Some(mut entry) = entries.next() {
+        let EntryKind::Segment(segment, header) = entry.kind else {
+            unreachable!();
+        };
+        let kind = segment.kind;
+        if let Some(file) = header
+            && let SegmentLen::Manual(len) = file.len
+            && len != entry.data.len()
+        {
+            eprintln!(
+                "segment {:?} at offset {} has length {}; expected {}",
+                Bytes(&file.path),
+                entry.offset,
+                BlockLen(entry.data.len()),
+                BlockLen(len),
+            );
+        }
+        if let Some(file) = header
+            && let Some(&Evidence::ResidueMismatch(residue)) = segment
+                .end
+                .evidence
+                .iter()
+                .find(|e| matches!(e, Evidence::ResidueMismatch(_)))
+        {
+            let block_end = (entry.offset + entry.data.len()).next_multiple_of(512);
+            eprintln!(
+                "segment {:?} at offset {} has residue which differs from the previous block; \
+                 expected length at least {}",
+                Bytes(&file.path),
+                entry.offset,
+                BlockLen(block_end.min(tape.len()) - residue - entry.offset),
+            );
         }
         println!(
-            "offset {:6} | len {:5} | {:8} | {} | {:11} | {:?}",
-            segment.offset,
-            segment.data.len(),
-            format!("{:?}", segment.kind),
-            if is_text(segment.data) {
-                "text"
-            } else {
-                "bin "
-            },
-            Magic::detect(segment.data)
+            "offset {:6} | len {:5} | {:8} | {} | {:11} | {:.2} {:.2} | {:?} | {} -> {}",
+            entry.offset,
+            entry.data.len(),
+            format!("{kind:?}"),
+            if is_text(&entry.data) { "text" } else { "bin " },
+            Magic::detect(&entry.data)
                 .map(|m| format!("{m:?}"))
                 .unwrap_or("none".to_owned()),
-            Bytes(&h.path_bytes()),
+            segment.start.confidence(),
+            segment.end.confidence(),
+            Bytes(entry.relative_path()),
+            segment.start,
+            segment.end,
         );
-        let data = if include_residue
-            && segment.kind == SegmentKind::Original
-            && let Some(next_segment) = segmenter.segments().get(i + 1)
-            && next_segment.kind == SegmentKind::Residue
+        if include_residue
+            && kind == SegmentKind::Original
+            && let Some(next) = entries
+                .next_if(|next| matches!(next.kind, EntryKind::Segment(s, _) if s.kind == SegmentKind::Residue))
         {
-            i += 1;
             const DELIM: &[u8] = b"[SPLIT]";
-            let mut data =
-                Vec::with_capacity(segment.data.len() + DELIM.len() + next_segment.data.len());
-            data.extend_from_slice(segment.data);
+            let mut data = Vec::with_capacity(entry.data.len() + DELIM.len() + next.data.len());
+            data.extend_from_slice(&entry.data);
             data.extend_from_slice(DELIM);
-            data.extend_from_slice(next_segment.data);
-            Cow::Owned(data)
-        } else {
-            Cow::Borrowed(segment.data)
-        };
-        h.set_mode(0o644);
-        h.set_size(data.len() as _);
-        h.set_cksum();
-        tar.append(&h, &*data).unwrap();
-        i += 1;
+            data.extend_from_slice(&next.data);
+            entry.data = Cow::Owned(data);
+        }
+        files.push(entry);
     }
+    let epoch = infer_epoch(v1_times(&files), None);
+    write_tar(&files, Some(epoch), File::create(tar_path).unwrap()).unwrap();
 }
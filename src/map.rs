@@ -0,0 +1,274 @@
+//! Rendering of a tape as a map of its blocks, as a self-contained SVG or HTML
+//! file.
+//!
+//! The tape is drawn as rows of 512-byte blocks, with each file as a run of
+//! rectangles coloured by its kind, magic number and whether it is text. Each
+//! rectangle has a title, which browsers show on hover, with the path, offset
+//! and length. Elements are written one per line in tape order, so that maps
+//! of different segmentations can be compared with a text diff.
+
+#![warn(missing_docs)]
+
+use std::io::{self, Write};
+
+use crate::{
+    archive::{EntryKind, TapeEntry},
+    detect::{Magic, is_text},
+    segment::SegmentKind,
+};
+
+/// The number of bytes in a block.
+const BLOCK: usize = 512;
+/// The number of blocks in a row.
+const ROW_BLOCKS: usize = 32;
+/// The width and height of a block in pixels.
+const CELL: usize = 24;
+/// The height of a row of the legend in pixels.
+const LEGEND_ROW: usize = 20;
+
+/// The style of an entry in a map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// An a.out binary or `#!` script.
+    Magic,
+    /// Text.
+    Text,
+    /// Other binary data.
+    Binary,
+    /// Residue from a previous block.
+    Residue,
+    /// Blocks of all NUL bytes.
+    AllNul,
+    /// Blocks of all 0xFF bytes.
+    AllFF,
+}
+
+impl Style {
+    /// All styles, in the order of the legend.
+    pub const ALL: [Style; 6] = [
+        Style::Magic,
+        Style::Text,
+        Style::Binary,
+        Style::Residue,
+        Style::AllNul,
+        Style::AllFF,
+    ];
+
+    /// Chooses the style for an entry.
+    pub fn of(entry: &TapeEntry<'_>) -> Self {
+        let kind = match entry.kind {
+            EntryKind::Segment(segment, _) => segment.kind,
+            EntryKind::Tap(_) | EntryKind::Tp(_) => SegmentKind::Original,
+        };
+        match kind {
+            SegmentKind::Residue => Style::Residue,
+            SegmentKind::AllNul => Style::AllNul,
+            SegmentKind::AllFF => Style::AllFF,
+            SegmentKind::Original if Magic::detect(&entry.data).is_some() => Style::Magic,
+            SegmentKind::Original if is_text(&entry.data) => Style::Text,
+            SegmentKind::Original => Style::Binary,
+        }
+    }
+
+    /// The class name used in the stylesheet.
+    pub fn class(self) -> &'static str {
+        match self {
+            Style::Magic => "magic",
+            Style::Text => "text",
+            Style::Binary => "binary",
+            Style::Residue => "residue",
+            Style::AllNul => "nul",
+            Style::AllFF => "ff",
+        }
+    }
+
+    fn fill(self) -> &'static str {
+        match self {
+            Style::Magic => "#e8a44a",
+            Style::Text => "#7cc47c",
+            Style::Binary => "#7fa7d9",
+            Style::Residue => "#c8c8c8",
+            Style::AllNul => "#ffffff",
+            Style::AllFF => "#404040",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Style::Magic => "a.out or #!",
+            Style::Text => "text",
+            Style::Binary => "binary",
+            Style::Residue => "residue",
+            Style::AllNul => "all NUL",
+            Style::AllFF => "all 0xFF",
+        }
+    }
+}
+
+/// Writes a map of the entries of a tape as an SVG image.
+pub fn write_svg<W: Write>(entries: &[TapeEntry<'_>], tape_len: usize, mut w: W) -> io::Result<()> {
+    let blocks = tape_len.div_ceil(BLOCK);
+    let rows = blocks.div_ceil(ROW_BLOCKS);
+    let label_width = 56;
+    let width = label_width + ROW_BLOCKS * CELL + 1;
+    let map_height = rows * CELL + 1;
+    let height = map_height + 8 + Style::ALL.len() * LEGEND_ROW;
+    writeln!(
+        w,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="monospace" font-size="11">"#,
+    )?;
+    writeln!(w, "<style>")?;
+    for style in Style::ALL {
+        writeln!(w, ".{} {{ fill: {} }}", style.class(), style.fill())?;
+    }
+    writeln!(w, ".unnamed {{ fill-opacity: 0.55 }}")?;
+    writeln!(w, "rect {{ stroke: #000000; stroke-width: 0.5 }}")?;
+    writeln!(w, ".grid {{ stroke: #00000030; stroke-width: 0.5 }}")?;
+    writeln!(w, "</style>")?;
+
+    for row in 0..rows {
+        let y = row * CELL;
+        writeln!(
+            w,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+            label_width - 6,
+            y + CELL / 2 + 4,
+            row * ROW_BLOCKS,
+        )?;
+    }
+
+    writeln!(w, r#"<g transform="translate({label_width} 0)">"#)?;
+    for entry in entries {
+        let style = Style::of(entry);
+        let named = match entry.kind {
+            EntryKind::Segment(_, header) => header.is_some(),
+            EntryKind::Tap(_) | EntryKind::Tp(_) => true,
+        };
+        let class = if named {
+            style.class().to_owned()
+        } else {
+            format!("{} unnamed", style.class())
+        };
+        let magic = match Magic::detect(&entry.data) {
+            Some(magic) => format!(" {magic:?}"),
+            None => String::new(),
+        };
+        let title = escape(&format!(
+            "{}\noffset {} len {} ({}{})",
+            String::from_utf8_lossy(&entry.path),
+            entry.offset,
+            entry.data.len(),
+            style.label(),
+            magic,
+        ));
+        writeln!(w, r#"<g class="{class}"><title>{title}</title>"#)?;
+        let end = entry.offset + entry.data.len();
+        let mut start = entry.offset;
+        while start < end {
+            let row = start / (BLOCK * ROW_BLOCKS);
+            let row_end = ((row + 1) * BLOCK * ROW_BLOCKS).min(end);
+            let col = start % (BLOCK * ROW_BLOCKS);
+            writeln!(
+                w,
+                r#"<rect x="{}" y="{}" width="{}" height="{CELL}"/>"#,
+                px(col),
+                row * CELL,
+                px(col + row_end - start) - px(col),
+            )?;
+            start = row_end;
+        }
+        writeln!(w, "</g>")?;
+    }
+    for block in 0..blocks {
+        let (x, y) = ((block % ROW_BLOCKS) * CELL, (block / ROW_BLOCKS) * CELL);
+        writeln!(w, r#"<path class="grid" d="M{x} {y}v{CELL}" fill="none"/>"#)?;
+    }
+    writeln!(w, "</g>")?;
+
+    for (i, style) in Style::ALL.into_iter().enumerate() {
+        let y = map_height + 8 + i * LEGEND_ROW;
+        writeln!(
+            w,
+            r#"<rect class="{}" x="{label_width}" y="{y}" width="{CELL}" height="{}"/><text x="{}" y="{}">{}</text>"#,
+            style.class(),
+            LEGEND_ROW - 6,
+            label_width + CELL + 6,
+            y + LEGEND_ROW - 9,
+            escape(style.label()),
+        )?;
+    }
+    writeln!(w, "</svg>")
+}
+
+/// Writes a map of the entries of a tape as an HTML page with the SVG inline.
+pub fn write_html<W: Write>(
+    entries: &[TapeEntry<'_>],
+    tape_len: usize,
+    title: &str,
+    mut w: W,
+) -> io::Result<()> {
+    let title = escape(title);
+    writeln!(w, "<!DOCTYPE html>")?;
+    writeln!(w, r#"<html lang="en">"#)?;
+    writeln!(
+        w,
+        r#"<head><meta charset="utf-8"><title>{title}</title></head>"#
+    )?;
+    writeln!(w, "<body>")?;
+    writeln!(w, "<h1>{title}</h1>")?;
+    write_svg(entries, tape_len, &mut w)?;
+    writeln!(w, "</body>")?;
+    writeln!(w, "</html>")
+}
+
+/// Converts a byte offset within a row to pixels.
+fn px(offset: usize) -> usize {
+    offset * CELL / BLOCK
+}
+
+/// Escapes text for XML, replacing control characters which XML 1.0 does not
+/// allow.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            _ if c.is_control() => escaped.push(char::REPLACEMENT_CHARACTER),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn map_s1() {
+    use crate::{archive::TapeArchive, segment::Segmenter};
+
+    let tape = std::fs::read("s1-bits").unwrap();
+    let mut segmenter = Segmenter::new(&tape, 512);
+    segmenter.segment_blocks();
+    let entries = segmenter.entries();
+    let mut svg = Vec::new();
+    write_svg(&entries, tape.len(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.ends_with("</svg>\n"));
+    assert_eq!(svg.matches("<title>").count(), entries.len());
+    assert_eq!(
+        svg.matches(r#"<path class="grid""#).count(),
+        tape.len() / 512
+    );
+    assert!(svg.contains(
+        "<g class=\"magic unnamed\"><title>segments/33792.bin\noffset 33792 len 424 \
+         (a.out or #! V1Normal)</title>\n<rect x=\"48\" y=\"48\" width=\"19\" height=\"24\"/>"
+    ));
+
+    assert_eq!(
+        escape("a<b & \"c\"\0"),
+        "a&lt;b &amp; &quot;c&quot;\u{FFFD}"
+    );
+}
@@ -0,0 +1,130 @@
+//! Mapping of user IDs to names, such as from an `/etc/passwd` recovered from a
+//! tape.
+
+#![warn(missing_docs)]
+
+use std::{borrow::Cow, collections::BTreeMap, fmt};
+
+use anyhow::{Result, bail};
+
+use crate::{archive::TapeEntry, util::Bytes};
+
+/// A mapping from user IDs to names.
+pub trait UserNames {
+    /// The name of the user with the given ID, if known.
+    fn user_name(&self, uid: u16) -> Option<&str>;
+}
+
+/// User names from an `/etc/passwd` or `/etc/uids` file.
+///
+/// When several names share an ID, like `root` and `eroot` in s2, the first is
+/// used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Passwd {
+    names: BTreeMap<u16, String>,
+}
+
+/// Displays a user ID as its name, if known, or otherwise as the number.
+pub struct User<'a>(pub u16, pub &'a dyn UserNames);
+
+impl Passwd {
+    /// Constructs an empty mapping.
+    pub fn new() -> Self {
+        Passwd::default()
+    }
+
+    /// Parses a passwd file, with lines of the form `name:password:uid:...`.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        Passwd::parse_lines(data, 2)
+    }
+
+    /// Parses a V1 `/etc/uids` file, with lines of the form `name:uid`.
+    pub fn parse_uids(data: &[u8]) -> Result<Self> {
+        Passwd::parse_lines(data, 1)
+    }
+
+    fn parse_lines(data: &[u8], uid_field: usize) -> Result<Self> {
+        let mut passwd = Passwd::new();
+        for line in data.split(|&b| b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let fields = line.split(|&b| b == b':').collect::<Vec<_>>();
+            let (Some(name), Some(uid)) = (fields.first(), fields.get(uid_field)) else {
+                bail!("missing fields: {:?}", Bytes(line));
+            };
+            let Some(uid) = str::from_utf8(uid).ok().and_then(|uid| uid.parse().ok()) else {
+                bail!("invalid uid: {:?}", Bytes(line));
+            };
+            passwd.insert(uid, String::from_utf8_lossy(name));
+        }
+        Ok(passwd)
+    }
+
+    /// Finds `/etc/passwd`, or otherwise `/etc/uids`, in the files of a tape
+    /// and parses it.
+    pub fn from_entries(entries: &[TapeEntry<'_>]) -> Option<Self> {
+        let find = |path: &[u8]| entries.iter().find(|e| *e.path == *path);
+        if let Some(entry) = find(b"/etc/passwd")
+            && let Ok(passwd) = Passwd::parse(&entry.data)
+        {
+            Some(passwd)
+        } else if let Some(entry) = find(b"/etc/uids") {
+            Passwd::parse_uids(&entry.data).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Adds a name for a user ID, unless it already has one.
+    pub fn insert<'a>(&mut self, uid: u16, name: impl Into<Cow<'a, str>>) {
+        self.names
+            .entry(uid)
+            .or_insert_with(|| name.into().into_owned());
+    }
+}
+
+impl UserNames for Passwd {
+    fn user_name(&self, uid: u16) -> Option<&str> {
+        self.names.get(&uid).map(String::as_str)
+    }
+}
+
+impl UserNames for BTreeMap<u16, String> {
+    fn user_name(&self, uid: u16) -> Option<&str> {
+        self.get(&uid).map(String::as_str)
+    }
+}
+
+/// No names are known.
+impl UserNames for () {
+    fn user_name(&self, _uid: u16) -> Option<&str> {
+        None
+    }
+}
+
+impl fmt::Display for User<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1.user_name(self.0) {
+            Some(name) => f.pad(name),
+            None => f.pad(&self.0.to_string()),
+        }
+    }
+}
+
+#[test]
+fn passwd_s2() {
+    use crate::archive::open_dir;
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = open_dir(&tape).unwrap();
+    let passwd = Passwd::from_entries(&archive.entries()).unwrap();
+    assert_eq!(passwd.user_name(0), Some("root"));
+    assert_eq!(passwd.user_name(3), Some("bin"));
+    assert_eq!(passwd.user_name(10), Some("jack"));
+    assert_eq!(passwd.user_name(28), None);
+    assert_eq!(format!("{:<5}|", User(28, &passwd)), "28   |");
+
+    let uids = Passwd::parse_uids(b"root:0\nsys:1\nbin:3\nadm:3\n").unwrap();
+    assert_eq!(User(3, &uids).to_string(), "bin");
+}
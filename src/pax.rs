@@ -0,0 +1,181 @@
+//! PAX export of tap files, which preserves the V1 metadata that a ustar header
+//! cannot represent.
+//!
+//! Each file is preceded by PAX extended attributes with the raw V1 mode, uid
+//! and mtime, the epoch the mtime was interpreted in, and the tap block number,
+//! along with a `mtime` keeping the 1/60 second fraction. This is enough to
+//! rebuild the exact tap header from the tar alone, with [`parse_header`].
+//!
+//! When no epoch is given, the times are exported raw: only the V1 ticks are
+//! recorded and the ustar mtime is zero, so no epoch is implied.
+
+#![warn(missing_docs)]
+
+use std::io::{self, Write};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    tap::{Epoch, Header, Mode, Time},
+    util::Bytes,
+};
+
+/// PAX keyword for the raw V1 permission bits, in octal.
+pub const V1_MODE: &str = "UNIX.v1.mode";
+/// PAX keyword for the V1 user ID.
+pub const V1_UID: &str = "UNIX.v1.uid";
+/// PAX keyword for the raw V1 modification time, in 1/60 seconds.
+pub const V1_MTIME: &str = "UNIX.v1.mtime";
+/// PAX keyword for the year of the epoch which the modification time was
+/// interpreted in.
+pub const V1_EPOCH: &str = "UNIX.v1.epoch";
+/// PAX keyword for the index of the block which the file contents start at.
+pub const TAP_BLOCK: &str = "UNIX.tap.block";
+/// PAX keyword for the raw path, when it does not start with `/`.
+pub const TAP_PATH: &str = "UNIX.tap.path";
+/// PAX keyword for the unused bytes in hex, when they are not all zero.
+pub const TAP_UNUSED: &str = "UNIX.tap.unused";
+
+/// Computes the PAX extended attributes for a tap header, with the mtime in the
+/// given epoch or raw.
+pub fn extensions(header: &Header, epoch: Option<Epoch>) -> Vec<(&'static str, Vec<u8>)> {
+    let mut exts = Vec::new();
+    if let Some(epoch) = epoch {
+        let mtime = header.mtime().timestamp(epoch);
+        let mtime = format!("{}.{:09}", mtime.as_second(), mtime.subsec_nanosecond());
+        exts.push(("mtime", mtime.into_bytes()));
+    }
+    exts.extend([
+        (V1_MODE, format!("{:03o}", header.mode).into_bytes()),
+        (V1_UID, header.uid.to_string().into_bytes()),
+        (V1_MTIME, header.mtime.get().to_string().into_bytes()),
+    ]);
+    if let Some(epoch) = epoch {
+        exts.push((V1_EPOCH, epoch.year().to_string().into_bytes()));
+    }
+    exts.push((TAP_BLOCK, header.block().to_string().into_bytes()));
+    if !header.path().starts_with(b"/") {
+        exts.push((TAP_PATH, header.path().to_vec()));
+    }
+    if !header.unused.iter().all(|&b| b == 0) {
+        let hex = header.unused.iter().map(|b| format!("{b:02x}")).collect();
+        exts.push((TAP_UNUSED, String::into_bytes(hex)));
+    }
+    exts
+}
+
+/// Appends a file to a tar archive as a ustar header preceded by PAX extended
+/// attributes, with the mtime in the given epoch or raw.
+pub fn append<W: Write>(
+    tar: &mut tar::Builder<W>,
+    header: &Header,
+    contents: &[u8],
+    epoch: Option<Epoch>,
+) -> io::Result<()> {
+    let exts = extensions(header, epoch);
+    tar.append_pax_extensions(exts.iter().map(|(k, v)| (*k, &v[..])))?;
+    let mut h = header.to_ustar_header(epoch.unwrap_or(Epoch::Y1970));
+    if epoch.is_none() {
+        h.set_mtime(0);
+        h.set_cksum();
+    }
+    tar.append(&h, contents)
+}
+
+/// Rebuilds a tap header from the path of a tar entry and its PAX extended
+/// attributes, as written by [`append`].
+pub fn parse_header<'a>(
+    tar_path: &[u8],
+    size: u64,
+    exts: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+) -> Result<Header> {
+    let mut path = [b"/", tar_path].concat();
+    let (mut mode, mut uid, mut mtime, mut block, mut unused) = (None, None, None, None, None);
+    for (key, value) in exts {
+        let text = || match std::str::from_utf8(value) {
+            Ok(text) => Ok(text),
+            Err(_) => bail!("non-UTF-8 value for {key}: {:?}", Bytes(value)),
+        };
+        match key {
+            V1_MODE => mode = Some(u8::from_str_radix(text()?, 8)?),
+            V1_UID => uid = Some(text()?.parse::<u8>()?),
+            V1_MTIME => mtime = Some(text()?.parse::<u32>()?),
+            TAP_BLOCK => block = Some(text()?.parse::<u16>()?),
+            TAP_PATH => path = value.to_vec(),
+            TAP_UNUSED => {
+                let text = text()?;
+                if text.len() != 40 {
+                    bail!("{key} is not 20 bytes: {text:?}");
+                }
+                let mut bytes = [0; 20];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)?;
+                }
+                unused = Some(bytes);
+            }
+            _ => {}
+        }
+    }
+    let (Some(mode), Some(uid), Some(mtime), Some(block)) = (mode, uid, mtime, block) else {
+        bail!("missing V1 metadata for {:?}", Bytes(tar_path));
+    };
+    let Ok(size) = u16::try_from(size) else {
+        bail!("file too large: {:?} is {size} bytes", Bytes(tar_path));
+    };
+    let mut header = Header::new(&path, Mode(mode), uid, size, Time(mtime), block)?;
+    if let Some(unused) = unused {
+        header.unused = unused;
+        header.cksum = header.compute_cksum().into();
+    }
+    Ok(header)
+}
+
+#[test]
+fn round_trip_s2() {
+    use crate::tap::Archive;
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = Archive::parse(&tape).unwrap();
+    let mut tar = tar::Builder::new(Vec::new());
+    for header in archive.headers() {
+        append(
+            &mut tar,
+            header,
+            archive.contents(header),
+            Some(Epoch::Y1972),
+        )
+        .unwrap();
+    }
+    let tar = tar.into_inner().unwrap();
+
+    let mut tar = tar::Archive::new(&tar[..]);
+    let mut headers = archive.headers();
+    for entry in tar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path_bytes().into_owned();
+        let size = entry.size();
+        let exts = entry.pax_extensions().unwrap().unwrap();
+        let exts = exts
+            .map(|ext| {
+                let ext = ext.unwrap();
+                (ext.key().unwrap(), ext.value_bytes())
+            })
+            .collect::<Vec<_>>();
+        let header = parse_header(&path, size, exts).unwrap();
+        assert_eq!(&header, headers.next().unwrap());
+    }
+    assert!(headers.next().is_none());
+}
+
+#[test]
+fn fractional_mtime() {
+    let header = Header::new(b"/etc/passwd", Mode(0o16), 0, 0, Time(61), 25).unwrap();
+    let exts = extensions(&header, Some(Epoch::Y1970));
+    assert_eq!(exts[0], ("mtime", b"1.016666666".to_vec()));
+    let exts = extensions(&header, None);
+    assert!(
+        exts.iter()
+            .all(|&(key, _)| key != "mtime" && key != V1_EPOCH)
+    );
+    assert!(exts.contains(&(V1_MTIME, b"61".to_vec())));
+}
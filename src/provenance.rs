@@ -0,0 +1,181 @@
+//! Provenance of residue, tracing the stale bytes at the end of a block back to
+//! the file which they were first read from.
+//!
+//! The dump program read each block of a file into the same buffer and wrote
+//! the whole buffer, so the tail of a short block retains the bytes of the
+//! last block which filled that part of the buffer. Walking back through the
+//! blocks at the same position in the buffer, past any blocks where it was
+//! itself residue, reaches the file which those bytes belong to. The residue
+//! should then match that file byte for byte, which supports the boundaries
+//! on both sides.
+
+#![warn(missing_docs)]
+
+use std::ops::Range;
+
+use crate::segment::{Segment, SegmentKind};
+
+/// The provenance of a residue segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    /// The index of the residue segment.
+    pub residue: usize,
+    /// The sources of the residue, in order, covering all of it.
+    pub sources: Vec<Source>,
+}
+
+/// A run of residue bytes with a common source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    /// The byte range of the run in the tape.
+    pub range: Range<usize>,
+    /// The source of the run, or `None` when no earlier block filled that
+    /// part of the buffer.
+    pub origin: Option<Origin>,
+}
+
+/// Where a run of residue bytes was first read into the buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Origin {
+    /// The index of the segment which the bytes belong to.
+    pub segment: usize,
+    /// The byte offset in the tape of the start of the bytes.
+    pub offset: usize,
+    /// Whether the residue is identical to the bytes at the origin.
+    pub matches: bool,
+}
+
+impl Provenance {
+    /// The origins of the residue, skipping any runs without one.
+    pub fn origins(&self) -> impl Iterator<Item = &Origin> {
+        self.sources.iter().filter_map(|s| s.origin.as_ref())
+    }
+
+    /// Returns whether every byte of the residue was traced to a file and
+    /// matches it.
+    pub fn is_proven(&self) -> bool {
+        self.sources
+            .iter()
+            .all(|s| s.origin.as_ref().is_some_and(|o| o.matches))
+    }
+}
+
+/// Traces each residue segment back to the segments which its bytes were read
+/// from. The segments must be sorted by offset and not overlap.
+pub fn trace(tape: &[u8], segments: &[Segment<'_>], block_size: usize) -> Vec<Provenance> {
+    segments
+        .iter()
+        .enumerate()
+        .filter(|(_, segment)| segment.kind == SegmentKind::Residue)
+        .map(|(i, segment)| Provenance {
+            residue: i,
+            sources: trace_residue(tape, segments, block_size, segment),
+        })
+        .collect()
+}
+
+fn trace_residue(
+    tape: &[u8],
+    segments: &[Segment<'_>],
+    block_size: usize,
+    residue: &Segment<'_>,
+) -> Vec<Source> {
+    let mut sources: Vec<Source> = Vec::new();
+    let block_start = residue.offset - residue.offset % block_size;
+    for offset in residue.offset..residue.offset + residue.data.len() {
+        let origin =
+            origin_of(segments, block_size, offset, block_start).map(|(segment, from)| Origin {
+                segment,
+                offset: from,
+                matches: tape[from] == tape[offset],
+            });
+        if let Some(last) = sources.last_mut()
+            && continues(last, offset, origin.as_ref())
+        {
+            last.range.end = offset + 1;
+            continue;
+        }
+        sources.push(Source {
+            range: offset..offset + 1,
+            origin,
+        });
+    }
+    sources
+}
+
+/// Finds the byte which was in the buffer at the position of `offset` before
+/// the block at `block_start` was read, and the segment it belongs to.
+fn origin_of(
+    segments: &[Segment<'_>],
+    block_size: usize,
+    offset: usize,
+    block_start: usize,
+) -> Option<(usize, usize)> {
+    let mut from = offset;
+    for _ in 0..block_start / block_size {
+        from -= block_size;
+        let i = segments
+            .partition_point(|s| s.offset <= from)
+            .checked_sub(1)?;
+        let segment = &segments[i];
+        if from < segment.offset + segment.data.len() && segment.kind != SegmentKind::Residue {
+            return Some((i, from));
+        }
+    }
+    None
+}
+
+/// Returns whether the byte at `offset` extends the run of `last`.
+fn continues(last: &Source, offset: usize, origin: Option<&Origin>) -> bool {
+    match (&last.origin, origin) {
+        (None, None) => true,
+        (Some(a), Some(b)) => {
+            a.segment == b.segment
+                && a.matches == b.matches
+                && b.offset == a.offset + (offset - last.range.start)
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn trace_s1() {
+    use crate::split::Segmenter;
+
+    let tape = std::fs::read("s1-bits").unwrap();
+    let mut segmenter = Segmenter::new(&tape, 512);
+    segmenter.split_all();
+    let segments = segmenter.segments();
+    let provenance = trace(&tape, &segments, 512);
+    assert_eq!(
+        provenance.len(),
+        segments
+            .iter()
+            .filter(|s| s.kind == SegmentKind::Residue)
+            .count(),
+    );
+
+    // /etc/init fits in one block, so the residue after it is from the file
+    // before it.
+    let init = segments.iter().position(|s| s.offset == 33792).unwrap();
+    let p = provenance.iter().find(|p| p.residue == init + 1).unwrap();
+    assert!(p.is_proven());
+    assert_eq!(
+        p.sources,
+        [Source {
+            range: 34216..34304,
+            origin: Some(Origin {
+                segment: init - 1,
+                offset: 34216 - 512,
+                matches: true,
+            }),
+        }],
+    );
+
+    // Residue is not attributed to residue.
+    for p in &provenance {
+        for origin in p.origins() {
+            assert_ne!(segments[origin.segment].kind, SegmentKind::Residue);
+        }
+    }
+}
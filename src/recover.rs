@@ -0,0 +1,175 @@
+//! Forensic recovery of damaged tap headers.
+//!
+//! [`Archive`](crate::tap::Archive) only accepts headers whose checksums are
+//! valid, so a single flipped bit drops a file entirely. This finds headers
+//! which are otherwise plausible and suggests single-byte corrections which
+//! would make their checksums valid.
+
+#![warn(missing_docs)]
+
+use std::fmt;
+
+use crate::{tap::Header, util::Bytes};
+
+/// A header which is plausible, but has an invalid checksum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate<'a> {
+    /// The byte offset of the header in the tape.
+    pub offset: usize,
+    /// The header.
+    pub header: &'a Header,
+    /// The sum of the words of the header, which should be zero.
+    pub sum: u16,
+    /// Single-byte changes which would make the checksum valid and leave the
+    /// header plausible, with single-bit changes first and changes which set
+    /// unused bytes last.
+    pub corrections: Vec<Correction>,
+}
+
+/// A change to a single byte of a header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Correction {
+    /// The index of the byte in the header.
+    pub index: usize,
+    /// The current value.
+    pub from: u8,
+    /// The corrected value.
+    pub to: u8,
+}
+
+/// Scans every 64-byte slot of a tape for plausible tap headers with invalid
+/// checksums.
+pub fn scan(tape: &[u8]) -> Vec<Candidate<'_>> {
+    let mut candidates = Vec::new();
+    for (i, raw) in tape.chunks_exact(64).enumerate() {
+        let header: &Header = <&[u8; 64]>::try_from(raw).unwrap().into();
+        if header.valid() || !plausible(header, tape.len()) {
+            continue;
+        }
+        let sum = header.cksum().wrapping_sub(header.compute_cksum());
+        candidates.push(Candidate {
+            offset: i * 64,
+            header,
+            sum,
+            corrections: corrections(header, sum, tape.len()),
+        });
+    }
+    candidates
+}
+
+/// Returns whether a header looks like a tap header, ignoring its checksum.
+///
+/// The path must be absolute, printable ASCII, and padded with NULs; the mode
+/// must only have V1 permission bits; and the contents must be within the tape
+/// and after the boot block.
+pub fn plausible(header: &Header, tape_len: usize) -> bool {
+    let path = header.path();
+    path.len() >= 2
+        && path[0] == b'/'
+        && path.iter().all(|&b| b.is_ascii_graphic())
+        && header.path[path.len()..].iter().all(|&b| b == 0)
+        && header.mode & !0o77 == 0
+        && header.block() != 0
+        && header.range().end <= tape_len
+}
+
+fn corrections(header: &Header, sum: u16, tape_len: usize) -> Vec<Correction> {
+    let raw = header.as_bytes();
+    let mut corrections = Vec::new();
+    for (index, &from) in raw.iter().enumerate() {
+        // The change in the word needed to bring the sum to zero.
+        let need = sum.wrapping_neg();
+        let to = if index % 2 == 0 {
+            let to = (from as u16).wrapping_add(need);
+            let Ok(to) = u8::try_from(to) else {
+                continue;
+            };
+            to
+        } else {
+            if need & 0xFF != 0 {
+                continue;
+            }
+            from.wrapping_add((need >> 8) as u8)
+        };
+        let mut fixed = *raw;
+        fixed[index] = to;
+        let fixed = Header::from(fixed);
+        debug_assert!(fixed.valid());
+        if plausible(&fixed, tape_len) {
+            corrections.push(Correction { index, from, to });
+        }
+    }
+    // Unused bytes are zero on intact tapes, so prefer corrections that leave
+    // them so.
+    corrections.sort_by_key(|c| ((42..62).contains(&c.index) && c.to != 0, !c.is_single_bit()));
+    corrections
+}
+
+impl Correction {
+    /// Whether the correction flips a single bit.
+    pub fn is_single_bit(&self) -> bool {
+        (self.from ^ self.to).count_ones() == 1
+    }
+}
+
+impl fmt::Display for Candidate<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "header at offset {}: path {:?}, checksum sums to {:#06x}",
+            self.offset,
+            Bytes(self.header.path()),
+            self.sum,
+        )?;
+        for c in &self.corrections {
+            write!(f, "\n  {c}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self.index {
+            0..32 => "path",
+            32 => "mode",
+            33 => "uid",
+            34..36 => "size",
+            36..40 => "mtime",
+            40..42 => "block",
+            42..62 => "unused",
+            _ => "cksum",
+        };
+        write!(
+            f,
+            "byte {} ({field}): {:#04x} -> {:#04x}",
+            self.index, self.from, self.to,
+        )?;
+        if self.is_single_bit() {
+            write!(f, " (bit {})", (self.from ^ self.to).trailing_zeros())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn scan_s2() {
+    let mut tape = std::fs::read("s2-bits").unwrap();
+    assert_eq!(scan(&tape), []);
+
+    // Flip a bit in the path of /bin/ls.
+    tape[896 + 5] ^= 0x04;
+    let candidates = scan(&tape);
+    assert_eq!(candidates.len(), 1);
+    let candidate = &candidates[0];
+    assert_eq!(candidate.offset, 896);
+    assert_eq!(candidate.header.path(), b"/bin/hs");
+    assert_eq!(candidate.sum, 0xFC00);
+    let fix = Correction {
+        index: 5,
+        from: b'h',
+        to: b'l',
+    };
+    assert!(candidate.corrections.contains(&fix));
+    assert!(candidate.corrections[0].is_single_bit());
+}
@@ -1,18 +1,19 @@
-use std::{collections::HashSet, fmt, ops::Range};
+use std::{collections::HashSet, fmt, str::FromStr};
 
 use anyhow::{Result, bail};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, de::Error as _};
 
 use crate::{
-    detect::{AOut, Magic, is_text},
+    archive::Mtime,
+    detect::Magic,
     interval::IntervalSet,
+    split,
     util::{BlockLen, Bytes},
 };
 
 pub struct Segmenter<'a> {
     tape: &'a [u8],
     block_size: usize,
-    prev_block: &'a [u8],
     segments: Vec<Segment<'a>>,
     headers: Vec<Option<SegmentHeader>>,
     header_intervals: IntervalSet,
@@ -24,6 +25,43 @@ pub struct Segment<'a> {
     pub data: &'a [u8],
     pub offset: usize,
     pub kind: SegmentKind,
+    /// The evidence for the start of the segment.
+    pub start: Boundary,
+    /// The evidence for the end of the segment.
+    pub end: Boundary,
+}
+
+/// The evidence for a segment boundary.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Boundary {
+    pub evidence: Vec<Evidence>,
+}
+
+/// Evidence that a segment starts or ends at a boundary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Evidence {
+    /// The start or end of the tape.
+    TapeEdge,
+    /// A header given for a file.
+    Header,
+    /// A block-aligned magic number.
+    Magic(Magic),
+    /// The size of an a.out binary from its header.
+    AOutSize,
+    /// A residue of the given length, which matches the previous block.
+    Residue(usize),
+    /// The tail of the block after a file given by a header differs from the
+    /// previous block, so the length in the header is likely wrong. It holds
+    /// the length of the residue which does match, if any.
+    ResidueMismatch(usize),
+    /// A run of blocks of all NUL bytes.
+    AllNul,
+    /// A run of blocks of all 0xFF bytes.
+    AllFF,
+    /// A LF was taken back from the residue to terminate a text file.
+    LfAdjusted,
+    /// NULs which could have been a boundary were joined into the segment.
+    NulJoined,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -34,6 +72,8 @@ pub enum SegmentKind {
     AllFF,
 }
 
+/// A file given for a segment, as in `s1-segments.csv`. Only the path, offset
+/// and length are required.
 #[derive(Clone, Deserialize, PartialEq, Eq)]
 pub struct SegmentHeader {
     #[serde(alias = "Path", with = "serde_bytes")]
@@ -42,8 +82,34 @@ pub struct SegmentHeader {
     pub offset: usize,
     #[serde(alias = "Length", alias = "length")]
     pub len: SegmentLen,
+    /// The POSIX permission bits, written in octal.
+    #[serde(default, alias = "Mode", deserialize_with = "de_mode")]
+    pub mode: Option<u16>,
+    /// The user ID.
+    #[serde(default, alias = "Uid", alias = "UID")]
+    pub uid: Option<u16>,
+    /// The modification time, written as raw V1 ticks or in RFC 3339 format.
+    #[serde(default, alias = "Mtime", deserialize_with = "de_parse")]
+    pub mtime: Option<Mtime>,
+    /// The known file which the segment was matched with.
+    #[serde(default, alias = "Reference", deserialize_with = "de_string")]
+    pub reference: Option<String>,
+    /// The confidence in the identification.
+    #[serde(default, alias = "Confidence")]
+    pub confidence: Option<Confidence>,
+    /// Free-form notes on the file.
+    #[serde(default, alias = "Notes", deserialize_with = "de_string")]
+    pub notes: Option<String>,
+    /// Tags for the file, written separated by spaces.
+    #[serde(default, alias = "Tags", deserialize_with = "de_tags")]
+    pub tags: Vec<String>,
 }
 
+/// A confidence from 0 to 1, which is never NaN.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, PartialOrd)]
+#[serde(try_from = "f64")]
+pub struct Confidence(f64);
+
 #[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
 pub enum SegmentLen {
     #[serde(alias = "auto")]
@@ -52,12 +118,90 @@ pub enum SegmentLen {
     Manual(usize),
 }
 
+impl SegmentHeader {
+    /// Creates a header with only a path, offset and length.
+    pub fn new(path: Vec<u8>, offset: usize, len: SegmentLen) -> Self {
+        SegmentHeader {
+            path,
+            offset,
+            len,
+            mode: None,
+            uid: None,
+            mtime: None,
+            reference: None,
+            confidence: None,
+            notes: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl Confidence {
+    /// Creates a confidence, if it is from 0 to 1.
+    pub fn new(confidence: f64) -> Option<Self> {
+        (0.0..=1.0)
+            .contains(&confidence)
+            .then_some(Confidence(confidence))
+    }
+
+    /// The confidence as a number from 0 to 1.
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl Eq for Confidence {}
+
+impl TryFrom<f64> for Confidence {
+    type Error = String;
+
+    fn try_from(confidence: f64) -> Result<Self, Self::Error> {
+        Confidence::new(confidence)
+            .ok_or_else(|| format!("confidence must be from 0 to 1: {confidence}"))
+    }
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Deserializes an optional string, which is absent when empty.
+fn de_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(d)?.filter(|s| !s.is_empty()))
+}
+
+/// Deserializes an optional octal mode.
+fn de_mode<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u16>, D::Error> {
+    de_string(d)?
+        .map(|s| {
+            u16::from_str_radix(&s, 8).map_err(|_| D::Error::custom(format!("invalid mode: {s:?}")))
+        })
+        .transpose()
+}
+
+/// Deserializes an optional value with [`FromStr`].
+fn de_parse<'de, D: Deserializer<'de>, T: FromStr<Err = anyhow::Error>>(
+    d: D,
+) -> Result<Option<T>, D::Error> {
+    de_string(d)?
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+/// Deserializes space-separated tags.
+fn de_tags<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    Ok(de_string(d)?
+        .map(|s| s.split_whitespace().map(str::to_owned).collect())
+        .unwrap_or_default())
+}
+
 impl<'a> Segmenter<'a> {
     pub fn new(tape: &'a [u8], block_size: usize) -> Self {
         Segmenter {
             tape,
             block_size,
-            prev_block: &[],
             segments: Vec::new(),
             headers: vec![None; tape.len().div_ceil(block_size)],
             header_intervals: IntervalSet::new(0..tape.len()),
@@ -67,7 +211,7 @@ impl<'a> Segmenter<'a> {
 
     pub fn add_header(&mut self, header: SegmentHeader) -> Result<()> {
         let offset = header.offset;
-        if offset % self.block_size != 0 {
+        if !offset.is_multiple_of(self.block_size) {
             bail!("header is not block-aligned");
         }
         if let SegmentLen::Manual(len) = header.len {
@@ -83,7 +227,7 @@ impl<'a> Segmenter<'a> {
 
     #[track_caller]
     pub fn header_for_offset(&self, offset: usize) -> Option<&SegmentHeader> {
-        if offset % self.block_size == 0 && offset < self.tape.len() {
+        if offset.is_multiple_of(self.block_size) && offset < self.tape.len() {
             self.headers[offset / self.block_size].as_ref()
         } else {
             None
@@ -95,11 +239,20 @@ impl<'a> Segmenter<'a> {
         self.headers[block].as_ref()
     }
 
+    pub fn tape(&self) -> &'a [u8] {
+        self.tape
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
     pub fn segments(&self) -> &[Segment<'a>] {
         &self.segments
     }
 
-    /// Partitions a tape into segments which are likely to be files.
+    /// Partitions a tape into segments which are likely to be files, by
+    /// resolving the splits found by [`split::Segmenter`].
     ///
     /// It exploits the behavior of the dumping program that was used, which
     /// evidently read files using a 512-byte buffer and directly dumped the
@@ -107,143 +260,90 @@ impl<'a> Segmenter<'a> {
     /// last block of a file, its tail is left unchanged, so by comparing blocks
     /// for common tails, file boundaries can be quite accurately identified.
     pub fn segment_blocks(&mut self) {
-        let mut segment_start = 0;
-        let mut block_start = 0;
-        while block_start < self.tape.len() {
-            let block_end = (block_start + self.block_size).min(self.tape.len());
-            let block = &self.tape[block_start..block_end];
-
-            if let Some(header) = self.header_for_offset(block_start)
-                && let SegmentLen::Manual(len) = header.len
-            {
-                if let Some(aout) = AOut::parse(block)
-                    && let Some(aout_size) = aout.file_size()
-                {
-                    // Some files have a larger size than predicted by their
-                    // a.out headers. This may be the case for those with
-                    // undefined external symbols?
-                    assert!(aout_size <= len);
-                }
-                let range = header.offset..header.offset + len;
-                if segment_start != block_start {
-                    self.push(segment_start..block_start, SegmentKind::Original);
-                }
-                self.push(range.clone(), SegmentKind::Original);
-                block_start = range.end.next_multiple_of(self.block_size);
-                segment_start = block_start;
-                self.prev_block = &self.tape[block_start - self.block_size..block_start];
-                // TODO: Handle the residue.
-                continue;
-            }
-
-            if Magic::detect(block).is_some() {
-                if segment_start != block_start {
-                    self.push(segment_start..block_start, SegmentKind::Original);
-                }
-                segment_start = block_start;
-            }
-            if let Some(aout) = AOut::parse(block)
-                && let Some(aout_size) = aout.file_size()
-            {
-                self.push(
-                    segment_start..segment_start + aout_size,
-                    SegmentKind::Original,
-                );
-            }
-
-            // Check for blocks that are all NUL or all 0xFF.
-            let uniform = if let Some(end) = self.check_uniform(block_start, 0) {
-                Some((end, SegmentKind::AllNul))
-            } else if let Some(end) = self.check_uniform(block_start, 0xFF) {
-                Some((end, SegmentKind::AllFF))
-            } else {
-                None
+        let mut splitter = split::Segmenter::new(self.tape, self.block_size);
+        for header in self.headers.iter().flatten() {
+            let len = match header.len {
+                SegmentLen::Manual(len) => Some(len),
+                SegmentLen::Auto => None,
             };
-            if let Some((uniform_end, kind)) = uniform {
-                if segment_start != block_start {
-                    // Join NUL blocks surrounded by zeros.
-                    if kind == SegmentKind::AllNul
-                        && self.tape.get(block_start.wrapping_sub(1)) == Some(&0)
-                        && self.tape.get(uniform_end) == Some(&0)
-                    {
-                        block_start = uniform_end;
-                        continue;
-                    }
-                    self.push(segment_start..block_start, SegmentKind::Original);
-                }
-                self.push(block_start..uniform_end, kind);
-                self.prev_block = &self.tape[uniform_end - self.block_size..uniform_end];
-                segment_start = uniform_end;
-                block_start = uniform_end;
-                continue;
-            }
+            splitter.add_header(header.offset, len);
+        }
+        splitter.split_all();
+        self.segments = splitter.segments();
+    }
+}
 
-            // Check whether this block and the previous have a common suffix.
-            if !self.prev_block.is_empty() {
-                let mut eq_index = 0;
-                for i in (0..block.len()).rev() {
-                    if block[i] != self.prev_block[i] {
-                        eq_index = i + 1;
-                        break;
-                    }
-                }
-                let mut split = block_start + eq_index;
-                // Take back a LF from the residue, if it is a text segment
-                // which does not end with LF.
-                if segment_start != split
-                    && self.tape[split - 1] != b'\n'
-                    && self.tape[split] == b'\n'
-                    && is_text(&self.tape[segment_start..split])
-                {
-                    split += 1;
-                }
-                // Join residue of all NUL, if it is surrounded by NUL.
-                else if block_end < self.tape.len()
-                    && self.tape[split - 1..block_end + 1].iter().all(|&b| b == 0)
-                {
-                    split = block_end;
-                }
-                // Only treat it as residue, if it is long enough. Apparent
-                // residue of length 1 or 2 is usually a false positive.
-                if split + 2 < block_end {
-                    if segment_start != split {
-                        self.push(segment_start..split, SegmentKind::Original);
-                    }
-                    self.push(split..block_end, SegmentKind::Residue);
-                    segment_start = block_end;
-                }
-            }
+impl Boundary {
+    /// Creates a boundary with the given evidence.
+    pub fn new(evidence: impl Into<Vec<Evidence>>) -> Self {
+        Boundary {
+            evidence: evidence.into(),
+        }
+    }
 
-            self.prev_block = block;
-            block_start += self.block_size;
+    /// The confidence in this boundary, from 0 to 1.
+    ///
+    /// Each piece of evidence is treated as independent, so the confidence is
+    /// the chance that not all of them are wrong. Adjustments then reduce it by
+    /// 10% each, since they override the raw evidence, and a residue mismatch
+    /// halves it, since it contradicts the evidence.
+    pub fn confidence(&self) -> f64 {
+        let mut doubt = 1.0;
+        let mut adjust = 1.0;
+        for evidence in &self.evidence {
+            match evidence.weight() {
+                Some(weight) => doubt *= 1.0 - weight,
+                None if matches!(evidence, Evidence::ResidueMismatch(_)) => adjust *= 0.5,
+                None => adjust *= 0.9,
+            }
         }
+        (1.0 - doubt) * adjust
+    }
+}
 
-        if segment_start != self.tape.len() {
-            self.push(segment_start..self.tape.len(), SegmentKind::Original);
+impl Evidence {
+    /// The confidence that this evidence alone gives to a boundary, or `None`
+    /// for adjustments.
+    ///
+    /// A residue of `n` bytes gives `1 - 2^(-n/8)`, so 3 bytes give 0.23 and 32
+    /// bytes give 0.94, since short common suffixes are often coincidences.
+    pub fn weight(self) -> Option<f64> {
+        match self {
+            Evidence::TapeEdge | Evidence::Header => Some(1.0),
+            Evidence::AllFF => Some(0.95),
+            Evidence::Magic(_) | Evidence::AllNul => Some(0.9),
+            Evidence::AOutSize => Some(0.8),
+            Evidence::Residue(len) => Some(1.0 - (-(len as f64) / 8.0).exp2()),
+            Evidence::ResidueMismatch(_) | Evidence::LfAdjusted | Evidence::NulJoined => None,
         }
     }
+}
 
-    fn check_uniform(&mut self, block_start: usize, byte: u8) -> Option<usize> {
-        let mut end = block_start;
-        for block in self.tape[block_start..].chunks(self.block_size) {
-            if !block.iter().all(|&b| b == byte) {
-                break;
-            }
-            end += block.len();
-            if self.header_for_offset(end).is_some() {
-                break;
-            }
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}", self.confidence())?;
+        for (i, evidence) in self.evidence.iter().enumerate() {
+            f.write_str(if i == 0 { " " } else { "+" })?;
+            write!(f, "{evidence}")?;
         }
-        (end != block_start).then_some(end)
+        Ok(())
     }
+}
 
-    #[track_caller]
-    fn push(&mut self, range: Range<usize>, kind: SegmentKind) {
-        self.segments.push(Segment {
-            data: &self.tape[range.clone()],
-            offset: range.start,
-            kind,
-        });
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evidence::TapeEdge => f.write_str("tape-edge"),
+            Evidence::Header => f.write_str("header"),
+            Evidence::Magic(magic) => write!(f, "magic({magic:?})"),
+            Evidence::AOutSize => f.write_str("a.out-size"),
+            Evidence::Residue(len) => write!(f, "residue({len})"),
+            Evidence::ResidueMismatch(len) => write!(f, "residue-mismatch({len})"),
+            Evidence::AllNul => f.write_str("all-nul"),
+            Evidence::AllFF => f.write_str("all-ff"),
+            Evidence::LfAdjusted => f.write_str("lf-adjusted"),
+            Evidence::NulJoined => f.write_str("nul-joined"),
+        }
     }
 }
 
@@ -253,7 +353,9 @@ impl fmt::Debug for Segment<'_> {
             .field("offset", &self.offset)
             .field("len", &BlockLen(self.data.len()))
             .field("kind", &self.kind)
-            .field("data", &Bytes(&self.data))
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("data", &Bytes(self.data))
             .finish()
     }
 }
@@ -264,6 +366,13 @@ impl fmt::Debug for SegmentHeader {
             .field("path", &Bytes(&self.path))
             .field("offset", &self.offset)
             .field("len", &self.len)
+            .field("mode", &self.mode.map(|mode| format!("{mode:04o}")))
+            .field("uid", &self.uid)
+            .field("mtime", &self.mtime)
+            .field("reference", &self.reference)
+            .field("confidence", &self.confidence)
+            .field("notes", &self.notes)
+            .field("tags", &self.tags)
             .finish()
     }
 }
@@ -2,7 +2,16 @@
 
 #![warn(missing_docs)]
 
-use std::{cmp::Ordering, fmt, ops::Range};
+use std::{
+    cmp::{Ordering, Reverse},
+    fmt, mem,
+    ops::Range,
+};
+
+use crate::{
+    detect::{AOut, Magic, is_text},
+    segment::{Boundary, Evidence, Segment, SegmentKind},
+};
 
 /// Tool for segmenting a tape into likely files.
 pub struct Segmenter<'t> {
@@ -20,6 +29,9 @@ pub struct Split {
     offsets: Range<usize>,
     /// The split strategy.
     kind: SplitKind,
+    /// Offset of the data which suggested this split, e.g., the start of the
+    /// a.out header for [`SplitKind::AOutSize`].
+    origin: usize,
 }
 
 /// Split strategies for segmenting files in a tape.
@@ -35,6 +47,8 @@ pub enum SplitKind {
     NulResidue,
     /// line feeds at the start of a residue.
     LfResidue,
+    /// Blocks of all NUL bytes.
+    Nul,
     /// The size of an a.out binary determined from its header. It seems to
     /// under-count sometimes, perhaps for object files with undefined external
     /// symbols.
@@ -43,6 +57,8 @@ pub enum SplitKind {
     Magic,
     /// Blocks of all 0xFF bytes.
     FF,
+    /// The start or end of a file given by a header.
+    Header,
 }
 
 /// Split direction.
@@ -66,9 +82,29 @@ impl<'t> Segmenter<'t> {
         }
     }
 
+    /// Adds splits for a file given by a header, with its length if known.
+    /// Headers should be added before splitting.
+    pub fn add_header(&mut self, offset: usize, len: Option<usize>) {
+        self.splits
+            .push(Split::new_point(offset, SplitKind::Header));
+        if let Some(len) = len {
+            self.splits
+                .push(Split::new_point(offset + len, SplitKind::Header).with_origin(offset));
+        }
+        self.splits.sort();
+    }
+
+    /// The splits performed so far, sorted by offsets.
+    pub fn splits(&self) -> &[Split] {
+        &self.splits
+    }
+
     /// Performs all supported splits.
     pub fn split_all(&mut self) {
         self.split_blocks();
+        self.split_magic();
+        self.split_aout_size();
+        self.split_nul_blocks();
         self.split_ff_blocks();
         self.split_lf_residue();
         self.split_nul_residue();
@@ -121,25 +157,60 @@ impl<'t> Segmenter<'t> {
         }
     }
 
+    /// Inserts split points at blocks which start with an a.out or `#!` magic
+    /// number.
+    pub fn split_magic(&mut self) {
+        for (i, block) in self.tape.chunks(self.block_size).enumerate() {
+            if Magic::detect(block).is_some() {
+                self.splits
+                    .push(Split::new_point(i * self.block_size, SplitKind::Magic));
+            }
+        }
+        self.splits.sort();
+    }
+
+    /// Inserts split points at the ends of a.out binaries predicted by the
+    /// sizes in their headers, with the header as the origin.
+    pub fn split_aout_size(&mut self) {
+        for (i, block) in self.tape.chunks(self.block_size).enumerate() {
+            let start = i * self.block_size;
+            if let Some(aout) = AOut::parse(block)
+                && let Some(size) = aout.file_size()
+                && start + size <= self.tape.len()
+            {
+                self.splits
+                    .push(Split::new_point(start + size, SplitKind::AOutSize).with_origin(start));
+            }
+        }
+        self.splits.sort();
+    }
+
+    /// Inserts split points around runs of blocks of all NUL bytes.
+    pub fn split_nul_blocks(&mut self) {
+        self.split_uniform_blocks(0, SplitKind::Nul);
+    }
+
     /// Inserts split points around runs of blocks of all 0xFF bytes.
     pub fn split_ff_blocks(&mut self) {
+        self.split_uniform_blocks(0xFF, SplitKind::FF);
+    }
+
+    fn split_uniform_blocks(&mut self, byte: u8, kind: SplitKind) {
         let was_empty = self.splits.is_empty();
         let mut block_start = 0;
-        let mut prev_ff = false;
+        let mut prev_uniform = false;
 
         while block_start < self.tape.len() {
             let block_end = (block_start + self.block_size).min(self.tape.len());
-            let all_ff = self.tape[block_start..block_end].iter().all(|&b| b == 0xFF);
-            if all_ff != prev_ff {
-                self.splits
-                    .push(Split::new_point(block_start, SplitKind::FF));
+            let uniform = self.tape[block_start..block_end].iter().all(|&b| b == byte);
+            if uniform != prev_uniform {
+                self.splits.push(Split::new_point(block_start, kind));
             }
-            prev_ff = all_ff;
+            prev_uniform = uniform;
             block_start = block_end;
         }
-        if prev_ff {
-            self.splits
-                .push(Split::new_point(self.tape.len(), SplitKind::FF));
+        if prev_uniform {
+            self.splits.push(Split::new_point(self.tape.len(), kind));
         }
 
         if !was_empty {
@@ -197,21 +268,348 @@ impl<'t> Segmenter<'t> {
         }
         self.splits.sort();
     }
+
+    /// Resolves the splits into a consistent partition of the tape into
+    /// segments.
+    ///
+    /// Blocks are visited in order and, at each, the candidate splits in the
+    /// block are taken by [direction](SplitKind::direction) and, among those,
+    /// by priority. First, the splits suggested by the data at the start of the
+    /// block can start a file:
+    ///
+    /// - A [header](SplitKind::Header) always starts a file. If it has a
+    ///   length, the file spans to its end split and the tail of its last block
+    ///   is split out as residue and checked against the previous block.
+    /// - A [magic number](SplitKind::Magic) starts a file. If the a.out size
+    ///   predicts its [end](SplitKind::AOutSize), lower-priority splits before
+    ///   then are ignored.
+    /// - [FF](SplitKind::FF) and [NUL](SplitKind::Nul) runs become their own
+    ///   segments, except that NUL runs surrounded by NULs are joined with the
+    ///   file they are in.
+    ///
+    /// Then the splits which can end a file are tried:
+    ///
+    /// - The file ends at its predicted [a.out size](SplitKind::AOutSize),
+    ///   unless the residue of that block shows it to have under-counted.
+    /// - Otherwise, a [residue](SplitKind::Residue) ends the file and is split
+    ///   out, adjusted to give text files a final LF or to join residue of NULs
+    ///   surrounded by NULs. Residue of 1 or 2 bytes is usually a false
+    ///   positive and is ignored.
+    pub fn segments(&self) -> Vec<Segment<'t>> {
+        let mut out = Partition {
+            tape: self.tape,
+            segments: Vec::new(),
+            start: 0,
+            evidence: Boundary::new([Evidence::TapeEdge]),
+            joined: false,
+        };
+        let mut block_start = 0;
+        // The end of the current file predicted by its a.out header.
+        let mut claim_end = None;
+        'blocks: while block_start < self.tape.len() {
+            let block_end = (block_start + self.block_size).min(self.tape.len());
+            let block = &self.tape[block_start..block_end];
+
+            // The splits in the block, including an a.out size which ends the
+            // file with the block, highest priority first.
+            let mut candidates = self
+                .splits_in(block_start..block_end + 1)
+                .filter(|s| s.start() < block_end || s.kind == SplitKind::AOutSize)
+                .collect::<Vec<_>>();
+            candidates.sort_by_key(|s| Reverse(s.kind));
+
+            let starts = candidates.iter().filter(|s| {
+                s.kind.direction() != SplitDir::End
+                    && s.start() == block_start
+                    && s.origin == block_start
+            });
+            for split in starts {
+                match split.kind {
+                    SplitKind::Header => {
+                        let header = Boundary::new([Evidence::Header]);
+                        out.split(block_start, SegmentKind::Original, header);
+                        claim_end = None;
+                        if let Some(end) = self.find_from(block_start, SplitKind::Header) {
+                            block_start = self.split_header_end(&mut out, end.start(), block_end);
+                            continue 'blocks;
+                        }
+                    }
+                    SplitKind::Magic => {
+                        let magic = Evidence::Magic(Magic::detect(block).unwrap());
+                        out.split(block_start, SegmentKind::Original, Boundary::new([magic]));
+                        claim_end = self
+                            .find_from(block_start, SplitKind::AOutSize)
+                            .map(Split::start);
+                    }
+                    _ => {}
+                }
+            }
+
+            // Uniform blocks are found by their contents, since their splits
+            // only mark the edges of runs. A NUL block within an a.out is not
+            // split, as the a.out size has priority.
+            let claimed = claim_end.is_some_and(|end| end > block_end);
+            let uniform = if block.iter().all(|&b| b == 0xFF) {
+                Some(SplitKind::FF)
+            } else if block.iter().all(|&b| b == 0) && !claimed {
+                Some(SplitKind::Nul)
+            } else {
+                None
+            };
+            if let Some(kind) = uniform {
+                block_start = self.split_uniform(&mut out, block_start, kind);
+                claim_end = None;
+                continue;
+            }
+
+            let residue = candidates
+                .iter()
+                .find(|s| s.kind == SplitKind::Residue)
+                .copied();
+            let ends = candidates
+                .iter()
+                .filter(|s| s.kind.direction() != SplitDir::Start);
+            for split in ends {
+                match split.kind {
+                    SplitKind::AOutSize if claim_end == Some(split.start()) => {
+                        // Trust the a.out size, unless the file evidently
+                        // extends past it into the residue.
+                        let end = split.start();
+                        claim_end = None;
+                        if residue.is_some_and(|r| r.start() <= end) || end == block_end {
+                            let mut boundary = Boundary::new([Evidence::AOutSize]);
+                            if residue.is_some() {
+                                boundary.evidence.push(Evidence::Residue(block_end - end));
+                            }
+                            out.split(end, SegmentKind::Original, boundary.clone());
+                            out.split(block_end, SegmentKind::Residue, boundary);
+                            break;
+                        }
+                    }
+                    SplitKind::Residue if !claimed && block_start != 0 => {
+                        self.split_residue(&mut out, split.start(), block_end);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            block_start = block_end;
+        }
+
+        let tape_end = Boundary::new([Evidence::TapeEdge]);
+        out.split(self.tape.len(), SegmentKind::Original, tape_end);
+        out.segments
+    }
+
+    /// Ends a file given by a header at `end`, splitting out the tail of its
+    /// last block as residue, and returns the offset of the next block to
+    /// visit.
+    fn split_header_end(&self, out: &mut Partition<'t>, end: usize, block_end: usize) -> usize {
+        let header = Boundary::new([Evidence::Header]);
+        let tail_end = end.next_multiple_of(self.block_size).min(self.tape.len());
+        if end < tail_end {
+            let mut boundary = header.clone();
+            boundary
+                .evidence
+                .extend(self.residue_evidence(end, tail_end));
+            out.split(end, SegmentKind::Original, boundary.clone());
+            out.split(tail_end, SegmentKind::Residue, boundary);
+        } else {
+            out.split(end, SegmentKind::Original, header.clone());
+        }
+        let next = tail_end.max(block_end);
+        if out.start != next {
+            out.skip(next, header);
+        }
+        next
+    }
+
+    /// Splits out a run of uniform blocks starting at `block_start`, or joins
+    /// it with the open file if it is NULs surrounded by NULs, and returns the
+    /// end of the run. Runs end early at a header.
+    fn split_uniform(&self, out: &mut Partition<'t>, block_start: usize, kind: SplitKind) -> usize {
+        let mut run_end = self.next_point(block_start, kind);
+        if let Some(header) = self.next_point_opt(block_start, SplitKind::Header) {
+            run_end = run_end.min(header.next_multiple_of(self.block_size));
+        }
+        // Join NUL blocks surrounded by zeros.
+        if out.start != block_start
+            && kind == SplitKind::Nul
+            && self.tape[block_start - 1] == 0
+            && self.tape.get(run_end) == Some(&0)
+        {
+            out.joined = true;
+            return run_end;
+        }
+        let (kind, evidence) = match kind {
+            SplitKind::FF => (SegmentKind::AllFF, Evidence::AllFF),
+            _ => (SegmentKind::AllNul, Evidence::AllNul),
+        };
+        out.split(
+            block_start,
+            SegmentKind::Original,
+            Boundary::new([evidence]),
+        );
+        out.split(run_end, kind, Boundary::new([evidence]));
+        run_end
+    }
+
+    /// Ends the open file at the residue starting at `split` and splits out
+    /// the residue to the end of the block.
+    fn split_residue(&self, out: &mut Partition<'t>, mut split: usize, block_end: usize) {
+        let mut boundary = Boundary::new([Evidence::Residue(block_end - split)]);
+        // Take back a LF from the residue, if it is a text segment which does
+        // not end with LF.
+        if let Some(lf) = self.find_in(split..split + 1, SplitKind::LfResidue)
+            && out.start != split
+            && is_text(&self.tape[out.start..split])
+        {
+            if lf.start() != split {
+                boundary.evidence.push(Evidence::LfAdjusted);
+            }
+            split = lf.start();
+        }
+        // Join residue of all NUL, if it is surrounded by NUL.
+        else if let Some(nul) = self.find_in(split..split + 1, SplitKind::NulResidue)
+            && nul.end() == block_end
+            && self.tape[split - 1] == 0
+            && self.tape.get(block_end) == Some(&0)
+        {
+            out.joined = true;
+            split = block_end;
+        }
+        // Only treat it as residue, if it is long enough. Apparent residue of
+        // length 1 or 2 is usually a false positive.
+        if split + 2 < block_end {
+            out.split(split, SegmentKind::Original, boundary.clone());
+            out.split(block_end, SegmentKind::Residue, boundary);
+        }
+    }
+
+    /// Checks the tail of a block after the end of a file given by a header
+    /// against the previous block. It agrees when the residue found there
+    /// starts at or before the end of the file. The first block has no previous
+    /// block to check against.
+    fn residue_evidence(&self, end: usize, tail_end: usize) -> Option<Evidence> {
+        let block_start = end - end % self.block_size;
+        if block_start == 0 {
+            return None;
+        }
+        Some(
+            match self.find_in(block_start..tail_end, SplitKind::Residue) {
+                Some(residue) if residue.start() <= end => Evidence::Residue(tail_end - end),
+                Some(residue) => Evidence::ResidueMismatch(tail_end - residue.start()),
+                None => Evidence::ResidueMismatch(0),
+            },
+        )
+    }
+
+    /// Finds a split of the kind which starts in the range.
+    fn find_in(&self, range: Range<usize>, kind: SplitKind) -> Option<&Split> {
+        self.splits_in(range).find(|s| s.kind == kind)
+    }
+
+    fn splits_in(&self, range: Range<usize>) -> impl Iterator<Item = &Split> {
+        let i = self.splits.partition_point(|s| s.start() < range.start);
+        self.splits[i..]
+            .iter()
+            .take_while(move |s| s.start() < range.end)
+    }
+
+    /// Finds a split of the kind which was suggested by the data at the
+    /// offset, but is after it.
+    fn find_from(&self, origin: usize, kind: SplitKind) -> Option<&Split> {
+        self.splits
+            .iter()
+            .find(|s| s.kind == kind && s.origin == origin && s.start() > origin)
+    }
+
+    /// The next split point of the kind after the offset.
+    fn next_point_opt(&self, offset: usize, kind: SplitKind) -> Option<usize> {
+        let i = self.splits.partition_point(|s| s.start() <= offset);
+        self.splits[i..]
+            .iter()
+            .find(|s| s.kind == kind)
+            .map(Split::start)
+    }
+
+    /// The next split point of the kind after the offset, or the end of the
+    /// tape.
+    fn next_point(&self, offset: usize, kind: SplitKind) -> usize {
+        self.next_point_opt(offset, kind).unwrap_or(self.tape.len())
+    }
+}
+
+/// Segments being partitioned from a tape.
+struct Partition<'t> {
+    tape: &'t [u8],
+    segments: Vec<Segment<'t>>,
+    /// The start of the open segment.
+    start: usize,
+    /// The evidence for the start of the open segment.
+    evidence: Boundary,
+    /// Whether NULs were joined into the open segment.
+    joined: bool,
+}
+
+impl<'t> Partition<'t> {
+    /// Ends the open segment at `end` with the evidence for that boundary, and
+    /// opens the next segment there. When the segment would be empty, the
+    /// evidence is combined with that for its start.
+    fn split(&mut self, end: usize, kind: SegmentKind, mut boundary: Boundary) {
+        if self.start == end {
+            for evidence in boundary.evidence {
+                if !self.evidence.evidence.contains(&evidence) {
+                    self.evidence.evidence.push(evidence);
+                }
+            }
+            return;
+        }
+        if self.joined {
+            boundary.evidence.push(Evidence::NulJoined);
+        }
+        self.segments.push(Segment {
+            data: &self.tape[self.start..end],
+            offset: self.start,
+            kind,
+            start: mem::replace(&mut self.evidence, boundary.clone()),
+            end: boundary,
+        });
+        self.start = end;
+        self.joined = false;
+        self.evidence.evidence.retain(|&e| e != Evidence::NulJoined);
+    }
+
+    /// Skips bytes which are not in any segment and opens the next segment.
+    fn skip(&mut self, start: usize, boundary: Boundary) {
+        self.start = start;
+        self.evidence = boundary;
+        self.joined = false;
+    }
 }
 
 impl Split {
     /// Creates a new split over the given range.
     pub fn new(offsets: Range<usize>, kind: SplitKind) -> Self {
         assert!(offsets.start <= offsets.end);
-        Split { offsets, kind }
+        let origin = offsets.start;
+        Split {
+            offsets,
+            kind,
+            origin,
+        }
     }
 
     /// Creates a new split at the given offset.
     pub fn new_point(offset: usize, kind: SplitKind) -> Self {
-        Split {
-            offsets: offset..offset,
-            kind,
-        }
+        Split::new(offset..offset, kind)
+    }
+
+    /// Sets the offset of the data which suggested this split.
+    pub fn with_origin(mut self, origin: usize) -> Self {
+        self.origin = origin;
+        self
     }
 
     /// Range of offsets at which this split can be performed.
@@ -233,6 +631,11 @@ impl Split {
     pub fn kind(&self) -> SplitKind {
         self.kind
     }
+
+    /// Offset of the data which suggested this split.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
 }
 
 impl PartialOrd for Split {
@@ -246,6 +649,7 @@ impl Ord for Split {
         (self.offsets.start.cmp(&other.offsets.start))
             .then(self.offsets.end.cmp(&other.offsets.end))
             .then(self.kind.cmp(&other.kind))
+            .then(self.origin.cmp(&other.origin))
     }
 }
 
@@ -258,6 +662,9 @@ impl fmt::Debug for Split {
             s.field("offsets", &self.offsets);
         }
         s.field("kind", &self.kind);
+        if self.origin != self.offsets.start {
+            s.field("origin", &self.origin);
+        }
         s.finish()
     }
 }
@@ -270,9 +677,85 @@ impl SplitKind {
             SplitKind::Residue => SplitDir::End,
             SplitKind::NulResidue => SplitDir::End,
             SplitKind::LfResidue => SplitDir::End,
+            SplitKind::Nul => SplitDir::Both,
             SplitKind::AOutSize => SplitDir::End,
             SplitKind::Magic => SplitDir::Both,
             SplitKind::FF => SplitDir::Both,
+            SplitKind::Header => SplitDir::Both,
         }
     }
 }
+
+#[test]
+fn segments_s1() {
+    let tape = std::fs::read("s1-bits").unwrap();
+    let mut segmenter = Segmenter::new(&tape, 512);
+    segmenter.split_all();
+    let segments = segmenter.segments();
+    assert!(
+        segments
+            .windows(2)
+            .all(|w| w[0].offset + w[0].data.len() <= w[1].offset)
+    );
+
+    // The a.out size of /etc/init takes priority over the residue 2 bytes
+    // before it.
+    let init = segments.iter().position(|s| s.offset == 33792).unwrap();
+    assert_eq!(segments[init].data.len(), 424);
+    assert_eq!(segments[init + 1].kind, SegmentKind::Residue);
+    assert_eq!(segments[init + 1].offset, 34216);
+    assert_eq!(
+        segments[init].end.evidence,
+        [Evidence::AOutSize, Evidence::Residue(88)],
+    );
+    assert!(segments[init].end.confidence() > segments[init].start.confidence());
+    // The apparent 3-byte residue within this a.out is ignored.
+    let aout = segments.iter().find(|s| s.offset == 229888).unwrap();
+    assert_eq!(aout.data.len(), 3752);
+}
+
+#[test]
+fn header_residue_s1() {
+    let tape = std::fs::read("s1-bits").unwrap();
+    let ed2 = |len| {
+        let mut segmenter = Segmenter::new(&tape, 512);
+        segmenter.add_header(50176, Some(len));
+        segmenter.split_all();
+        let segments = segmenter.segments();
+        let i = segments.iter().position(|s| s.offset == 50176).unwrap();
+        assert_eq!(segments[i].data.len(), len);
+        assert_eq!(segments[i + 1].kind, SegmentKind::Residue);
+        assert_eq!(segments[i + 1].offset, 50176 + len);
+        segments[i].end.evidence.clone()
+    };
+    assert_eq!(ed2(1834), [Evidence::Header, Evidence::Residue(214)]);
+    // Too short, so the tail has bytes from this file.
+    assert_eq!(
+        ed2(1800),
+        [Evidence::Header, Evidence::ResidueMismatch(214)]
+    );
+}
+
+#[test]
+fn header_auto_s1() {
+    let tape = std::fs::read("s1-bits").unwrap();
+    let mut segmenter = Segmenter::new(&tape, 512);
+    segmenter.add_header(2048, None);
+    segmenter.add_header(230400, None);
+    segmenter.split_all();
+    let segments = segmenter.segments();
+
+    // A header without a length splits the segment it is in, and the file
+    // runs on to the next split.
+    let i = segments.iter().position(|s| s.offset == 2048).unwrap();
+    assert_eq!(segments[i - 1].offset, 1024);
+    assert!(segments[i - 1].end.evidence.contains(&Evidence::Header));
+    assert_eq!(segments[i].start.evidence, [Evidence::Header]);
+    assert_eq!(segments[i].kind, SegmentKind::Original);
+    assert_eq!(segments[i + 1].offset, 1024 + 11776);
+
+    // It also cuts short an a.out predicted to extend past it.
+    let aout = segments.iter().find(|s| s.offset == 229888).unwrap();
+    assert_eq!(aout.data.len(), 512);
+    assert!(segments.iter().any(|s| s.offset == 230400));
+}
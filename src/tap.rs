@@ -7,11 +7,30 @@
 
 #![warn(missing_docs)]
 
-use std::{ffi::OsStr, fmt, mem, ops::Range, os::unix::ffi::OsStrExt, time::Duration, u32};
-
+use std::{
+    ffi::OsStr,
+    fmt::{self, Write as _},
+    ops::Range,
+    os::unix::ffi::OsStrExt,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
 use jiff::{Timestamp, civil::Date, tz::TimeZone};
 
-use crate::util::{Bytes, U16Le, U32Me};
+use crate::{
+    dir,
+    util::{Bytes, U16Le, U32Me, View, impl_view, sum_words},
+};
+
+pub use crate::dir::{Diagnostic, DiagnosticKind};
+
+/// The directory of a tap file.
+pub type Archive<'a> = dir::Archive<'a, Header>;
+
+/// A header in the directory of a tap file.
+pub type Entry<'a> = dir::Entry<'a, Header>;
 
 /// A file header in a tap file.
 #[derive(Clone, PartialEq, Eq)]
@@ -36,6 +55,15 @@ pub struct Header {
 }
 
 /// Permission bits in the Unix V1 format.
+///
+/// These are the low byte of the flags of a V1 inode. The directory and large
+/// file flags are in the high byte, so a tap file cannot record them.
+///
+/// It is displayed like the mode from `ls -l`, with one character for each bit
+/// from set-UID to world write, e.g., `-xrwr-` for 036, followed by any higher
+/// bits in octal, e.g., `-xrwr-+100` for 0136, and parsed from either that
+/// form or octal.
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Mode(pub u8);
 
 /// Timestamp in the Unix V1 format, i.e., 1/60 seconds since an [epoch](Epoch).
@@ -55,6 +83,7 @@ pub struct Mode(pub u8);
 /// > also makes it unlikely to be 1973.
 /// >
 /// > [[Warren Toomey](https://www.tuhs.org/Archive/Distributions/Research/1972_stuff/Readme)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Time(pub u32);
 
 /// The epoch of a Unix V1 timestamp.
@@ -70,7 +99,110 @@ pub enum Epoch {
     Y1973 = 3,
 }
 
+/// An editor which rewrites directory entries of a tap file in place.
+///
+/// Headers are edited through their setters, which keep the checksums valid.
+/// Headers whose checksums are already invalid are refused, so that a damaged
+/// header is not made to look authentic, unless explicitly borrowed with
+/// [`header_mut_unchecked`](Editor::header_mut_unchecked).
+pub struct Editor<'a> {
+    /// The tap file.
+    tape: &'a mut [u8],
+    /// The byte range of the directory.
+    dir: Range<usize>,
+}
+
+/// A writer which lays out a tap file.
+///
+/// The tape consists of a boot block, a directory of file headers, then the
+/// file contents in consecutive 512-byte blocks. Blocks are written through a
+/// single reused buffer, as by the program which wrote s2, so the tail of the
+/// last block of each file retains residue from the block before it.
+pub struct Writer {
+    /// The number of blocks reserved for the directory.
+    dir_blocks: u16,
+    /// The headers of the files written so far.
+    headers: Vec<Header>,
+    /// The blocks of file contents written so far.
+    data: Vec<u8>,
+    /// The buffer through which blocks are written.
+    buf: [u8; 512],
+}
+
 impl Header {
+    /// Constructs a file header and computes its checksum.
+    pub fn new(
+        path: &[u8],
+        mode: Mode,
+        uid: u8,
+        size: u16,
+        mtime: Time,
+        block: u16,
+    ) -> Result<Self> {
+        let mut header = Header {
+            path: [0; 32],
+            mode: mode.0,
+            uid,
+            size: size.into(),
+            mtime: mtime.0.into(),
+            block: block.into(),
+            unused: [0; 20],
+            cksum: 0.into(),
+        };
+        header.set_path(path)?;
+        Ok(header)
+    }
+
+    /// Sets the file path and updates the checksum.
+    pub fn set_path(&mut self, path: &[u8]) -> Result<()> {
+        if path.len() > 32 {
+            bail!("path longer than 32 bytes: {:?}", Bytes(path));
+        }
+        if path.is_empty() || path.contains(&0) {
+            bail!("invalid path: {:?}", Bytes(path));
+        }
+        self.path = [0; 32];
+        self.path[..path.len()].copy_from_slice(path);
+        self.update_cksum();
+        Ok(())
+    }
+
+    /// Sets the permission bits and updates the checksum.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode.0;
+        self.update_cksum();
+    }
+
+    /// Sets the user ID and updates the checksum.
+    pub fn set_uid(&mut self, uid: u8) {
+        self.uid = uid;
+        self.update_cksum();
+    }
+
+    /// Sets the length of the file contents and updates the checksum.
+    pub fn set_size(&mut self, size: u16) {
+        self.size.set(size);
+        self.update_cksum();
+    }
+
+    /// Sets the modification time and updates the checksum.
+    pub fn set_mtime(&mut self, mtime: Time) {
+        self.mtime.set(mtime.0);
+        self.update_cksum();
+    }
+
+    /// Sets the block which the file contents start at and updates the
+    /// checksum.
+    pub fn set_block(&mut self, block: u16) {
+        self.block.set(block);
+        self.update_cksum();
+    }
+
+    /// Recomputes the checksum, after the fields have been modified directly.
+    pub fn update_cksum(&mut self) {
+        self.cksum.set(self.compute_cksum());
+    }
+
     /// Parses a file header from a tap file.
     pub fn parse(raw: &[u8; 64]) -> Option<&Self> {
         let header: &Header = raw.into();
@@ -83,12 +215,17 @@ impl Header {
 
     /// Validates the header against its checksum.
     pub fn valid(&self) -> bool {
-        let bytes = unsafe { mem::transmute::<_, &[u8; 64]>(self) };
-        let mut cksum = 0u16;
-        for x in bytes.chunks_exact(2) {
-            cksum = cksum.wrapping_add(u16::from_le_bytes(x.try_into().unwrap()));
-        }
-        cksum == 0
+        sum_words(self.as_bytes()) == 0
+    }
+
+    /// Computes the checksum which makes the words of this header sum to zero.
+    pub fn compute_cksum(&self) -> u16 {
+        sum_words(&self.as_bytes()[..62]).wrapping_neg()
+    }
+
+    /// The raw bytes of this header.
+    pub fn as_bytes(&self) -> &[u8; 64] {
+        View::as_bytes(self).try_into().unwrap()
     }
 
     /// The file path.
@@ -136,27 +273,49 @@ impl Header {
         self.cksum.get()
     }
 
-    /// Converts this tap header to a tar header.
-    pub fn to_tar_header(&self) -> tar::Header {
-        let mut h = tar::Header::new_old();
-        h.set_path(OsStr::from_bytes(&self.path()[1..])).unwrap();
+    /// Converts this tap header to a tar header in the old format, with the
+    /// modification time in the given epoch.
+    pub fn to_tar_header(&self, epoch: Epoch) -> tar::Header {
+        self.fill_tar_header(tar::Header::new_old(), epoch)
+    }
+
+    /// Converts this tap header to a ustar header, with the modification time
+    /// in the given epoch.
+    pub fn to_ustar_header(&self, epoch: Epoch) -> tar::Header {
+        self.fill_tar_header(tar::Header::new_ustar(), epoch)
+    }
+
+    fn fill_tar_header(&self, mut h: tar::Header, epoch: Epoch) -> tar::Header {
+        let path = self.path().strip_prefix(b"/").unwrap_or(self.path());
+        h.set_path(OsStr::from_bytes(path)).unwrap();
         h.set_mode(self.mode().to_posix() as _);
         h.set_uid(self.uid as _);
         h.set_size(self.size() as _);
-        h.set_mtime(self.mtime().seconds(Epoch::Y1972) as _);
+        h.set_mtime(self.mtime().seconds(epoch) as _);
         h.set_cksum();
         h
     }
 }
 
+impl_view!(Header {
+    path: [u8; 32],
+    mode: u8,
+    uid: u8,
+    size: U16Le,
+    mtime: U32Me,
+    block: U16Le,
+    unused: [u8; 20],
+    cksum: U16Le
+});
+
 impl From<[u8; 64]> for Header {
     fn from(raw: [u8; 64]) -> Self {
-        unsafe { mem::transmute(raw) }
+        Header::read_from_bytes(&raw).unwrap()
     }
 }
-impl From<&[u8; 64]> for &Header {
-    fn from(raw: &[u8; 64]) -> Self {
-        unsafe { mem::transmute(raw) }
+impl<'a> From<&'a [u8; 64]> for &'a Header {
+    fn from(raw: &'a [u8; 64]) -> Self {
+        Header::ref_from_bytes(raw).unwrap()
     }
 }
 
@@ -177,16 +336,125 @@ impl fmt::Debug for Header {
     }
 }
 
+impl Writer {
+    /// Constructs a writer with the given number of directory blocks. Each
+    /// block holds 8 headers.
+    pub fn new(dir_blocks: u16) -> Self {
+        Writer {
+            dir_blocks,
+            headers: Vec::new(),
+            data: Vec::new(),
+            buf: [0; 512],
+        }
+    }
+
+    /// Appends a file to the tape and returns its header.
+    pub fn append(
+        &mut self,
+        path: &[u8],
+        mode: Mode,
+        uid: u8,
+        mtime: Time,
+        contents: &[u8],
+    ) -> Result<&Header> {
+        if self.headers.len() == self.dir_blocks as usize * 8 {
+            bail!("directory is full: {} blocks", self.dir_blocks);
+        }
+        let Ok(size) = u16::try_from(contents.len()) else {
+            bail!(
+                "file too large: {:?} is {} bytes",
+                Bytes(path),
+                contents.len()
+            );
+        };
+        let block = 1 + self.dir_blocks as usize + self.data.len() / 512;
+        let Ok(block) = u16::try_from(block) else {
+            bail!("tape too large: {:?} starts at block {block}", Bytes(path));
+        };
+        let header = Header::new(path, mode, uid, size, mtime, block)?;
+        for chunk in contents.chunks(512) {
+            self.buf[..chunk.len()].copy_from_slice(chunk);
+            self.data.extend_from_slice(&self.buf);
+        }
+        self.headers.push(header);
+        Ok(self.headers.last().unwrap())
+    }
+
+    /// The headers of the files written so far.
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
+    }
+
+    /// Lays out the tape, padding it with blocks of 0xFF to at least
+    /// `tape_blocks` blocks.
+    pub fn finish(self, tape_blocks: usize) -> Vec<u8> {
+        let dir_len = self.dir_blocks as usize * 512;
+        let mut tape = Vec::with_capacity((tape_blocks * 512).max(512 + dir_len + self.data.len()));
+        tape.resize(512, 0xFF);
+        for header in &self.headers {
+            tape.extend_from_slice(header.as_bytes());
+        }
+        tape.resize(512 + dir_len, 0);
+        tape.extend_from_slice(&self.data);
+        if tape.len() < tape_blocks * 512 {
+            tape.resize(tape_blocks * 512, 0xFF);
+        }
+        tape
+    }
+}
+
+impl<'a> Editor<'a> {
+    /// Opens a tap file for editing. Fails if it has no directory.
+    pub fn new(tape: &'a mut [u8]) -> Result<Self> {
+        let dir = Archive::parse(tape)?.dir_range();
+        Ok(Editor { tape, dir })
+    }
+
+    /// The tap file.
+    pub fn tape(&self) -> &[u8] {
+        self.tape
+    }
+
+    /// Mutably borrows the header at a byte offset in the directory. Fails if
+    /// its checksum is invalid, since editing it would make it valid.
+    pub fn header_mut(&mut self, offset: usize) -> Result<&mut Header> {
+        let header = self.header_mut_unchecked(offset)?;
+        if !header.valid() {
+            bail!(
+                "header at offset {offset} for {:?} has an invalid checksum",
+                Bytes(header.path()),
+            );
+        }
+        Ok(header)
+    }
+
+    /// Mutably borrows the header at a byte offset in the directory, even if
+    /// its checksum is invalid. The setters recompute the checksum, so this is
+    /// only for repairing damaged headers, like those found by
+    /// [`recover`](crate::recover).
+    pub fn header_mut_unchecked(&mut self, offset: usize) -> Result<&mut Header> {
+        if !offset.is_multiple_of(64) || !self.dir.contains(&offset) {
+            bail!("no header at offset {offset}");
+        }
+        Ok(Header::mut_from_bytes(&mut self.tape[offset..offset + 64]).unwrap())
+    }
+
+    /// Mutably borrows the header with the given path. Fails if its checksum
+    /// is invalid.
+    pub fn find_mut(&mut self, path: &[u8]) -> Result<&mut Header> {
+        let offset = self.tape[self.dir.clone()]
+            .chunks_exact(64)
+            .position(|raw| Header::ref_from_bytes(raw).unwrap().path() == path);
+        match offset {
+            Some(i) => self.header_mut(self.dir.start + i * 64),
+            None => bail!("no header with path {:?}", Bytes(path)),
+        }
+    }
+}
+
 #[rustfmt::skip]
 #[allow(dead_code)]
 mod mode {
-    pub const V1_SET_UID: u8     = 0o40;
-    pub const V1_EXEC: u8        = 0o20;
-    pub const V1_OWNER_READ: u8  = 0o10;
-    pub const V1_OWNER_WRITE: u8 = 0o04;
-    pub const V1_WORLD_READ: u8  = 0o02;
-    pub const V1_WORLD_WRITE: u8 = 0o01;
-
     pub const POSIX_SET_UID: u16     = 0o004000;
     pub const POSIX_SET_GID: u16     = 0o002000;
     pub const POSIX_STICKY: u16      = 0o001000;
@@ -201,28 +469,59 @@ mod mode {
     pub const POSIX_OTHER_EXEC: u16  = 0o000001;
 }
 
+#[rustfmt::skip]
+impl Mode {
+    /// Set user ID on execution.
+    pub const SET_UID: u8     = 0o40;
+    /// Executable.
+    pub const EXEC: u8        = 0o20;
+    /// Readable by the owner.
+    pub const OWNER_READ: u8  = 0o10;
+    /// Writable by the owner.
+    pub const OWNER_WRITE: u8 = 0o04;
+    /// Readable by others.
+    pub const WORLD_READ: u8  = 0o02;
+    /// Writable by others.
+    pub const WORLD_WRITE: u8 = 0o01;
+}
+
+/// The characters for each bit of the mode in `ls -l` form, from high to low.
+const MODE_CHARS: [(u8, u8); 6] = [
+    (Mode::SET_UID, b's'),
+    (Mode::EXEC, b'x'),
+    (Mode::OWNER_READ, b'r'),
+    (Mode::OWNER_WRITE, b'w'),
+    (Mode::WORLD_READ, b'r'),
+    (Mode::WORLD_WRITE, b'w'),
+];
+
 impl Mode {
+    /// Returns whether all of the given bits are set.
+    pub fn contains(self, bits: u8) -> bool {
+        self.0 & bits == bits
+    }
+
     /// Converts a POSIX mode to V1.
     pub fn from_posix(mode: u16) -> Mode {
         use mode::*;
         let (posix, mut v1) = (mode, 0);
         if posix & POSIX_SET_UID != 0 {
-            v1 |= V1_SET_UID;
+            v1 |= Mode::SET_UID;
         }
         if posix & (POSIX_OWNER_EXEC | POSIX_GROUP_EXEC | POSIX_OTHER_EXEC) != 0 {
-            v1 |= V1_EXEC;
+            v1 |= Mode::EXEC;
         }
         if posix & (POSIX_OWNER_READ) != 0 {
-            v1 |= V1_OWNER_READ;
+            v1 |= Mode::OWNER_READ;
         }
         if posix & (POSIX_OWNER_WRITE) != 0 {
-            v1 |= V1_OWNER_WRITE;
+            v1 |= Mode::OWNER_WRITE;
         }
         if posix & (POSIX_GROUP_READ | POSIX_OTHER_READ) != 0 {
-            v1 |= V1_WORLD_READ;
+            v1 |= Mode::WORLD_READ;
         }
         if posix & (POSIX_GROUP_WRITE | POSIX_OTHER_WRITE) != 0 {
-            v1 |= V1_WORLD_WRITE;
+            v1 |= Mode::WORLD_WRITE;
         }
         Mode(v1)
     }
@@ -231,22 +530,22 @@ impl Mode {
     pub fn to_posix(self) -> u16 {
         use mode::*;
         let (v1, mut posix) = (self.0, 0);
-        if v1 & V1_SET_UID != 0 {
+        if v1 & Mode::SET_UID != 0 {
             posix |= POSIX_SET_UID;
         }
-        if v1 & V1_EXEC != 0 {
+        if v1 & Mode::EXEC != 0 {
             posix |= POSIX_OWNER_EXEC | POSIX_GROUP_EXEC | POSIX_OTHER_EXEC;
         }
-        if v1 & V1_OWNER_READ != 0 {
+        if v1 & Mode::OWNER_READ != 0 {
             posix |= POSIX_OWNER_READ;
         }
-        if v1 & V1_OWNER_WRITE != 0 {
+        if v1 & Mode::OWNER_WRITE != 0 {
             posix |= POSIX_OWNER_WRITE;
         }
-        if v1 & V1_WORLD_READ != 0 {
+        if v1 & Mode::WORLD_READ != 0 {
             posix |= POSIX_GROUP_READ | POSIX_OTHER_READ;
         }
-        if v1 & V1_WORLD_WRITE != 0 {
+        if v1 & Mode::WORLD_WRITE != 0 {
             posix |= POSIX_GROUP_WRITE | POSIX_OTHER_WRITE;
         }
         posix
@@ -259,6 +558,58 @@ impl fmt::Debug for Mode {
     }
 }
 
+/// Formats the mode like `ls -l`, followed by any bits which are not
+/// permissions in octal.
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::with_capacity(6);
+        for (bit, c) in MODE_CHARS {
+            s.push(if self.contains(bit) { c as char } else { '-' });
+        }
+        let other = self.0 & !0o77;
+        if other != 0 {
+            write!(s, "+{other:o}")?;
+        }
+        f.pad(&s)
+    }
+}
+
+/// Parses a mode in `ls -l` form or octal.
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (bits, other) = match s.split_once('+') {
+            Some((bits, other)) => (bits, Some(other)),
+            None => (s, None),
+        };
+        if bits.len() == MODE_CHARS.len() && !bits.bytes().all(|b| b.is_ascii_digit()) {
+            let mut mode = 0;
+            for ((bit, c), b) in MODE_CHARS.into_iter().zip(bits.bytes()) {
+                match b {
+                    b'-' => {}
+                    _ if b == c => mode |= bit,
+                    _ => bail!("invalid mode: {s:?}"),
+                }
+            }
+            if let Some(other) = other {
+                match u8::from_str_radix(other, 8) {
+                    Ok(other) if other & 0o77 == 0 => mode |= other,
+                    _ => bail!("invalid mode: {s:?}"),
+                }
+            }
+            Ok(Mode(mode))
+        } else if other.is_none() {
+            match u8::from_str_radix(s, 8) {
+                Ok(mode) => Ok(Mode(mode)),
+                Err(_) => bail!("invalid mode: {s:?}"),
+            }
+        } else {
+            bail!("invalid mode: {s:?}")
+        }
+    }
+}
+
 impl Time {
     /// The time as a timestamp in the given epoch.
     pub fn timestamp(&self, epoch: Epoch) -> Timestamp {
@@ -298,11 +649,10 @@ impl fmt::Debug for Time {
 impl fmt::Display for Time {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let epoch = match f.precision() {
-            Some(1970) => Epoch::Y1970,
-            Some(1971) => Epoch::Y1971,
-            Some(1972) => Epoch::Y1972,
-            Some(1973) => Epoch::Y1973,
-            Some(_) => return Err(fmt::Error),
+            Some(year) => u16::try_from(year)
+                .ok()
+                .and_then(Epoch::from_year)
+                .ok_or(fmt::Error)?,
             None => Epoch::Y1972,
         };
         let t = self.timestamp_seconds(epoch).strftime("%F %T");
@@ -311,6 +661,19 @@ impl fmt::Display for Time {
 }
 
 impl Epoch {
+    /// All epochs, in chronological order.
+    pub const ALL: [Epoch; 4] = [Epoch::Y1970, Epoch::Y1971, Epoch::Y1972, Epoch::Y1973];
+
+    /// The epoch starting in the given year.
+    pub fn from_year(year: u16) -> Option<Self> {
+        Epoch::ALL.into_iter().find(|e| e.year() == year)
+    }
+
+    /// The year which the epoch starts in.
+    pub fn year(self) -> u16 {
+        1970 + self as u16
+    }
+
     /// The epoch as a timestamp.
     pub fn timestamp(self) -> Timestamp {
         Date::constant(1970 + self as i16, 1, 1)
@@ -320,6 +683,119 @@ impl Epoch {
     }
 }
 
+#[test]
+fn write_round_trip() {
+    let tape = std::fs::read("s2-bits").unwrap();
+    let mut writer = Writer::new(24);
+    for chunk in tape[512..25 * 512].chunks_exact(64) {
+        if let Some(h) = Header::parse(chunk.try_into().unwrap()) {
+            let written = writer
+                .append(h.path(), h.mode(), h.uid, h.mtime(), &tape[h.range()])
+                .unwrap();
+            assert_eq!(written, h);
+        }
+    }
+    assert_eq!(writer.headers().len(), 95);
+    assert!(writer.finish(tape.len() / 512) == tape);
+}
+
+#[test]
+fn archive_s2() {
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = Archive::parse(&tape).unwrap();
+    assert_eq!(archive.dir_range(), 512..25 * 512);
+    assert_eq!(archive.entries().len(), 95);
+    assert_eq!(archive.diagnostics(), []);
+}
+
+#[test]
+fn archive_diagnostics() {
+    let mut writer = Writer::new(1);
+    for path in [b"/a", b"/b", b"/a", b"/c"] {
+        writer
+            .append(path, Mode(0o16), 0, Time(0), &[b'x'; 600])
+            .unwrap();
+    }
+    let mut tape = writer.finish(0);
+    // Point /b at the second block of the first /a, extend it past the end of
+    // the tape, and corrupt the checksum of /c.
+    let b = Header::from(*tape[576..640].first_chunk::<64>().unwrap());
+    let b = Header::new(b.path(), b.mode(), b.uid, 2600, b.mtime(), 3).unwrap();
+    tape[576..640].copy_from_slice(b.as_bytes());
+    tape[704] ^= 1;
+    tape.truncate(8 * 512);
+
+    let archive = Archive::parse(&tape).unwrap();
+    assert_eq!(archive.entries().len(), 3);
+    let diag = |offset, kind| Diagnostic { offset, kind };
+    assert_eq!(
+        archive.diagnostics(),
+        [
+            diag(576, DiagnosticKind::OutOfBounds),
+            diag(576, DiagnosticKind::Overlap(512)),
+            diag(640, DiagnosticKind::DuplicatePath(512)),
+            diag(640, DiagnosticKind::Overlap(576)),
+            diag(704, DiagnosticKind::Checksum(0xFFFF)),
+        ],
+    );
+}
+
+#[test]
+fn edit_s2() {
+    let mut tape = std::fs::read("s2-bits").unwrap();
+    let mut editor = Editor::new(&mut tape).unwrap();
+    let header = editor.find_mut(b"/bin/ls").unwrap();
+    header.set_path(b"/bin/lsx").unwrap();
+    header.set_mode(Mode(0o17));
+    header.set_mtime(Time(12345));
+    assert!(header.valid());
+    assert!(editor.find_mut(b"/bin/ls").is_err());
+    assert!(editor.header_mut(896 + 1).is_err());
+
+    // Damaged headers are only editable when asked for explicitly.
+    let offset = Archive::parse(&tape)
+        .unwrap()
+        .entries()
+        .iter()
+        .find(|e| e.header.path() == b"/bin/date")
+        .unwrap()
+        .offset;
+    tape[offset + 1] = b'c';
+    let mut editor = Editor::new(&mut tape).unwrap();
+    assert!(editor.find_mut(b"/cin/date").is_err());
+    assert!(editor.header_mut(offset).is_err());
+    let header = editor.header_mut_unchecked(offset).unwrap();
+    header.set_path(b"/bin/date").unwrap();
+    assert!(header.valid());
+
+    let archive = Archive::parse(&tape).unwrap();
+    assert_eq!(archive.diagnostics(), []);
+    let entry = archive.entries().iter().find(|e| e.offset == 896).unwrap();
+    let header = entry.header;
+    assert_eq!(header.path(), b"/bin/lsx");
+    assert_eq!((header.mode(), header.mtime()), (Mode(0o17), Time(12345)));
+}
+
+#[test]
+fn mode_format() {
+    assert_eq!(Mode(0o36).to_string(), "-xrwr-");
+    assert_eq!(Mode(0o77).to_string(), "sxrwrw");
+    assert_eq!(Mode(0o100).to_string(), "------+100");
+    for mode in 0..=u8::MAX {
+        assert_eq!(Mode(mode).to_string().parse::<Mode>().unwrap(), Mode(mode));
+    }
+    assert_eq!("017".parse::<Mode>().unwrap(), Mode(0o17));
+    assert!("rwrwrw".parse::<Mode>().is_err());
+    assert!("------+1".parse::<Mode>().is_err());
+    assert!("017+100".parse::<Mode>().is_err());
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = Archive::parse(&tape).unwrap();
+    for h in archive.headers() {
+        assert_eq!(h.mode().to_string().parse::<Mode>().unwrap(), h.mode());
+    }
+}
+
 #[test]
 fn time_seconds_range() {
     let min = Time(0).timestamp(Epoch::Y1970).as_second();
@@ -0,0 +1,178 @@
+//! Chronologies of the files in a tape by modification time, for dating the
+//! development of the system.
+
+#![warn(missing_docs)]
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use anyhow::{Result, bail};
+use jiff::{SignedDuration, Span, civil::Date, tz::TimeZone};
+use serde::Serialize;
+
+use crate::{
+    archive::{Mtime, TapeEntry},
+    tap::Epoch,
+};
+
+/// The length of the periods which files are grouped into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Period {
+    /// Group by UTC day.
+    #[default]
+    Day,
+    /// Group by week, starting on Monday.
+    Week,
+}
+
+/// A row of a chronology.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Event {
+    /// The first day of the period which the file was modified in.
+    pub period: String,
+    /// The modification time in RFC 3339 format, with sub-second precision.
+    pub mtime: String,
+    /// The raw modification time in 1/60 seconds, for V1 times.
+    pub ticks: Option<u32>,
+    /// The file path, with invalid UTF-8 replaced.
+    pub path: String,
+    /// The byte offset in the tape of the start of the file.
+    pub offset: usize,
+    /// The length of the file contents.
+    pub len: usize,
+    /// For files modified in a batch with others, as when copied together, the
+    /// number of the batch, counting from 1 in chronological order.
+    pub batch: Option<usize>,
+}
+
+impl Period {
+    /// The first day of the period containing a date.
+    pub fn start(self, date: Date) -> Date {
+        match self {
+            Period::Day => date,
+            Period::Week => {
+                let days = date.weekday().to_monday_zero_offset();
+                date.checked_sub(Span::new().days(days)).unwrap()
+            }
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            _ => bail!("invalid period: {s}"),
+        }
+    }
+}
+
+/// Orders the entries with modification times chronologically, with V1 times
+/// in the given epoch. Ties remain in tape order.
+///
+/// Files modified within `batch_gap` of the previous file are grouped into a
+/// batch. No file in s2 shares its exact time with another, but a gap of a
+/// second groups runs like the `cp` of `/bin` on 1972-01-17.
+pub fn timeline(
+    entries: &[TapeEntry<'_>],
+    epoch: Epoch,
+    period: Period,
+    batch_gap: SignedDuration,
+) -> Vec<Event> {
+    let mut dated = entries
+        .iter()
+        .filter_map(|entry| Some((entry.mtime?.timestamp(epoch), entry)))
+        .collect::<Vec<_>>();
+    dated.sort_by_key(|&(t, _)| t);
+
+    let mut groups = Vec::with_capacity(dated.len());
+    for (i, &(t, _)) in dated.iter().enumerate() {
+        let joins = i != 0 && t.duration_since(dated[i - 1].0) <= batch_gap;
+        groups.push(if joins { groups[i - 1] } else { i });
+    }
+    let mut batches = HashMap::new();
+    for (i, &group) in groups.iter().enumerate() {
+        if groups.get(i + 1) == Some(&group) {
+            let next = batches.len() + 1;
+            batches.entry(group).or_insert(next);
+        }
+    }
+
+    dated
+        .iter()
+        .zip(groups)
+        .map(|(&(t, entry), group)| Event {
+            period: period.start(t.to_zoned(TimeZone::UTC).date()).to_string(),
+            mtime: t.to_string(),
+            ticks: match entry.mtime {
+                Some(Mtime::V1(time)) => Some(time.0),
+                _ => None,
+            },
+            path: String::from_utf8_lossy(&entry.path).into_owned(),
+            offset: entry.offset,
+            len: entry.data.len(),
+            batch: batches.get(&group).copied(),
+        })
+        .collect()
+}
+
+/// Writes a chronology as CSV.
+pub fn write_csv<W: Write>(events: &[Event], w: W) -> Result<()> {
+    let mut csv = csv::Writer::from_writer(w);
+    for event in events {
+        csv.serialize(event)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Writes a chronology as JSON.
+pub fn write_json<W: Write>(events: &[Event], mut w: W) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut w, events)?;
+    writeln!(w)
+}
+
+#[test]
+fn timeline_s2() {
+    use crate::archive::open_dir;
+
+    let tape = std::fs::read("s2-bits").unwrap();
+    let archive = open_dir(&tape).unwrap();
+    let entries = archive.entries();
+    let events = timeline(&entries, Epoch::Y1972, Period::Week, SignedDuration::ZERO);
+    assert_eq!(events.len(), 95);
+    assert!(events.windows(2).all(|w| w[0].ticks <= w[1].ticks));
+    assert!(events.iter().all(|e| e.batch.is_none()));
+
+    let events = timeline(
+        &entries,
+        Epoch::Y1972,
+        Period::Week,
+        SignedDuration::from_secs(1),
+    );
+    let batch = |path: &str| events.iter().find(|e| e.path == path).unwrap().batch;
+    assert_eq!(batch("/usr/jack/x.f"), None);
+    assert_eq!(batch("/bin/cat"), Some(1));
+    assert_eq!(batch("/bin/cp"), Some(1));
+    assert_eq!(batch("/bin/du"), None);
+    assert_eq!(batch("/bin/ln"), Some(2));
+
+    let mut csv = Vec::new();
+    write_csv(&events, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("period,mtime,ticks,path,offset,len,batch")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("1971-12-27,1972-01-01T00:33:51.866666666Z,121912,/core,275456,16448,"),
+    );
+}
@@ -0,0 +1,351 @@
+//! tp file decoding, for tapes written from the 3rd Edition onwards.
+//!
+//! The layout follows `tent` in `tp.h` from the 6th Edition. Compared to
+//! [tap](crate::tap), the mode is a full 16-bit inode mode, there is a group
+//! ID, sizes are 24 bits, and times are seconds since 1970.
+
+#![warn(missing_docs)]
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use jiff::Timestamp;
+
+use crate::{
+    dir::{self, DirHeader},
+    util::{Bytes, U16Le, U32Me, View, impl_view},
+};
+
+/// The directory of a tp file.
+pub type Archive<'a> = dir::Archive<'a, Header>;
+
+/// A header in the directory of a tp file.
+pub type Entry<'a> = dir::Entry<'a, Header>;
+
+/// A file header in a tp file.
+#[derive(Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct Header {
+    /// The file path.
+    pub path: [u8; 32],
+    /// The inode mode.
+    pub mode: U16Le,
+    /// The user ID.
+    pub uid: u8,
+    /// The group ID.
+    pub gid: u8,
+    /// Unused byte.
+    pub spare: u8,
+    /// The high byte of the length of the file contents.
+    pub size0: u8,
+    /// The low word of the length of the file contents.
+    pub size1: U16Le,
+    /// The modification time in seconds since 1970.
+    pub mtime: U32Me,
+    /// The index of the 512-byte block which the file contents start at.
+    pub block: U16Le,
+    /// Unused padding.
+    pub unused: [u8; 16],
+    /// The checksum of this header.
+    pub cksum: U16Le,
+}
+
+/// Inode mode in the format of the 3rd to 6th Editions.
+///
+/// It is displayed like the mode from `ls -l`, e.g., `-rwxr-xr-x`, and parsed
+/// from either that form or octal. The allocated and large file flags are not
+/// shown in that form.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Mode(pub u16);
+
+impl Header {
+    /// Constructs a file header and computes its checksum.
+    pub fn new(
+        path: &[u8],
+        mode: Mode,
+        uid: u8,
+        gid: u8,
+        size: u32,
+        mtime: u32,
+        block: u16,
+    ) -> Result<Self> {
+        if path.len() > 32 {
+            bail!("path longer than 32 bytes: {:?}", Bytes(path));
+        }
+        if path.is_empty() || path.contains(&0) {
+            bail!("invalid path: {:?}", Bytes(path));
+        }
+        if size >= 1 << 24 {
+            bail!("file too large: {:?} is {size} bytes", Bytes(path));
+        }
+        let mut path_buf = [0; 32];
+        path_buf[..path.len()].copy_from_slice(path);
+        let mut header = Header {
+            path: path_buf,
+            mode: mode.0.into(),
+            uid,
+            gid,
+            spare: 0,
+            size0: (size >> 16) as u8,
+            size1: (size as u16).into(),
+            mtime: mtime.into(),
+            block: block.into(),
+            unused: [0; 16],
+            cksum: 0.into(),
+        };
+        header.cksum = header.compute_cksum().into();
+        Ok(header)
+    }
+
+    /// Parses a file header from a tp file.
+    pub fn parse(raw: &[u8; 64]) -> Option<&Self> {
+        let header: &Header = raw.into();
+        if !raw.iter().all(|&b| b == 0) && header.valid() {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// The inode mode.
+    pub fn mode(&self) -> Mode {
+        Mode(self.mode.get())
+    }
+
+    /// The length of the file contents.
+    pub fn size(&self) -> u32 {
+        (self.size0 as u32) << 16 | self.size1.get() as u32
+    }
+
+    /// The modification time.
+    pub fn mtime(&self) -> Timestamp {
+        Timestamp::from_second(self.mtime.get() as _).unwrap()
+    }
+
+    /// The index of the 512-byte block which the file contents start at.
+    pub fn block(&self) -> u16 {
+        self.block.get()
+    }
+}
+
+impl_view!(Header {
+    path: [u8; 32],
+    mode: U16Le,
+    uid: u8,
+    gid: u8,
+    spare: u8,
+    size0: u8,
+    size1: U16Le,
+    mtime: U32Me,
+    block: U16Le,
+    unused: [u8; 16],
+    cksum: U16Le
+});
+
+impl From<[u8; 64]> for Header {
+    fn from(raw: [u8; 64]) -> Self {
+        Header::read_from_bytes(&raw).unwrap()
+    }
+}
+impl<'a> From<&'a [u8; 64]> for &'a Header {
+    fn from(raw: &'a [u8; 64]) -> Self {
+        Header::ref_from_bytes(raw).unwrap()
+    }
+}
+
+impl fmt::Debug for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Entry");
+        s.field("path", &Bytes(self.path()));
+        s.field("mode", &self.mode());
+        s.field("uid", &self.uid);
+        s.field("gid", &self.gid);
+        if self.spare != 0 {
+            s.field("spare", &self.spare);
+        }
+        s.field("size", &self.size());
+        s.field("mtime", &self.mtime());
+        s.field("block", &self.block());
+        if !self.unused.iter().all(|&b| b == 0) {
+            s.field("unused", &Bytes(&self.unused));
+        }
+        s.field("cksum", &self.cksum());
+        s.finish()
+    }
+}
+
+#[rustfmt::skip]
+impl Mode {
+    /// The inode is allocated.
+    pub const ALLOC: u16     = 0o100000;
+    /// Mask for the file type.
+    pub const FMT: u16       = 0o060000;
+    /// Directory file type.
+    pub const DIR: u16       = 0o040000;
+    /// Character special file type.
+    pub const CHAR: u16      = 0o020000;
+    /// Block special file type.
+    pub const BLOCK: u16     = 0o060000;
+    /// Large file, i.e., addressed through indirect blocks.
+    pub const LARGE: u16     = 0o010000;
+    /// Set user ID on execution.
+    pub const SET_UID: u16   = 0o004000;
+    /// Set group ID on execution.
+    pub const SET_GID: u16   = 0o002000;
+    /// Save text image after execution.
+    pub const STICKY: u16    = 0o001000;
+}
+
+/// The characters for the permission bits in `ls -l` form, from high to low.
+const PERM_CHARS: [u8; 9] = *b"rwxrwxrwx";
+
+impl Mode {
+    /// Returns whether all of the given bits are set.
+    pub fn contains(self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
+
+    /// Whether the inode is allocated.
+    pub fn is_allocated(self) -> bool {
+        self.contains(Mode::ALLOC)
+    }
+
+    /// Whether the file is a directory.
+    pub fn is_dir(self) -> bool {
+        self.0 & Mode::FMT == Mode::DIR
+    }
+
+    /// Whether the file is large.
+    pub fn is_large(self) -> bool {
+        self.contains(Mode::LARGE)
+    }
+
+    /// Converts the permission bits to POSIX. They have the same values, so
+    /// only the file type and the allocated and large file flags are dropped.
+    pub fn to_posix(self) -> u16 {
+        self.0 & 0o7777
+    }
+}
+
+impl fmt::Debug for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06o}", self.0)
+    }
+}
+
+/// Formats the mode like `ls -l`.
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = [b'-'; 10];
+        s[0] = match self.0 & Mode::FMT {
+            Mode::DIR => b'd',
+            Mode::CHAR => b'c',
+            Mode::BLOCK => b'b',
+            _ => b'-',
+        };
+        for (i, c) in PERM_CHARS.into_iter().enumerate() {
+            if self.contains(0o400 >> i) {
+                s[i + 1] = c;
+            }
+        }
+        for (bit, i, c) in [
+            (Mode::SET_UID, 3, b's'),
+            (Mode::SET_GID, 6, b's'),
+            (Mode::STICKY, 9, b't'),
+        ] {
+            if self.contains(bit) {
+                s[i] = if s[i] == b'-' {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                };
+            }
+        }
+        f.pad(str::from_utf8(&s).unwrap())
+    }
+}
+
+/// Parses a mode in `ls -l` form or octal.
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(mode) = u16::from_str_radix(s, 8) {
+            return Ok(Mode(mode));
+        }
+        let b = s.as_bytes();
+        if b.len() != 10 {
+            bail!("invalid mode: {s:?}");
+        }
+        let mut mode = match b[0] {
+            b'-' => 0,
+            b'd' => Mode::DIR,
+            b'c' => Mode::CHAR,
+            b'b' => Mode::BLOCK,
+            _ => bail!("invalid mode: {s:?}"),
+        };
+        for (i, c) in PERM_CHARS.into_iter().enumerate() {
+            let special = match i {
+                2 => Some(Mode::SET_UID),
+                5 => Some(Mode::SET_GID),
+                8 => Some(Mode::STICKY),
+                _ => None,
+            };
+            let special_char = if i == 8 { b't' } else { b's' };
+            match (b[i + 1], special) {
+                (b'-', _) => {}
+                (x, _) if x == c => mode |= 0o400 >> i,
+                (x, Some(bit)) if x == special_char => mode |= bit | 0o400 >> i,
+                (x, Some(bit)) if x == special_char.to_ascii_uppercase() => mode |= bit,
+                _ => bail!("invalid mode: {s:?}"),
+            }
+        }
+        Ok(Mode(mode))
+    }
+}
+
+#[test]
+fn mode_format() {
+    assert_eq!(Mode(0o100755).to_string(), "-rwxr-xr-x");
+    assert_eq!(Mode(0o140755).to_string(), "drwxr-xr-x");
+    assert_eq!(Mode(0o104711).to_string(), "-rws--x--x");
+    assert_eq!(Mode(0o007000).to_string(), "---S--S--T");
+    for mode in (0..0o10000).chain([Mode::DIR, Mode::CHAR, Mode::BLOCK]) {
+        assert_eq!(Mode(mode).to_string().parse::<Mode>().unwrap(), Mode(mode));
+    }
+    assert!(Mode(0o150644).is_large());
+    assert!(Mode(0o140755).is_dir());
+    assert!(!Mode(0o160755).is_dir());
+}
+
+#[test]
+fn parse_tp() {
+    use crate::dir::Format;
+
+    let files: [(&[u8], u32); 2] = [(b"/usr/sys/ken/main.c", 70000), (b"/etc/passwd", 40)];
+    let mut tape = vec![0; 512];
+    let mut dir = vec![0; 512];
+    let mut block = 2;
+    for (i, &(path, size)) in files.iter().enumerate() {
+        let h = Header::new(path, Mode(0o100644), 3, 1, size, 0x0A00_1234, block).unwrap();
+        dir[i * 64..i * 64 + 64].copy_from_slice(DirHeader::as_bytes(&h));
+        block += size.div_ceil(512) as u16;
+    }
+    tape.extend_from_slice(&dir);
+    tape.resize(block as usize * 512, b'x');
+
+    assert_eq!(Format::detect(&tape), Some(Format::Tp));
+    let archive = Archive::parse(&tape).unwrap();
+    assert_eq!(archive.diagnostics(), []);
+    assert_eq!(archive.dir_range(), 512..1024);
+    let headers = archive.headers().collect::<Vec<_>>();
+    assert_eq!(headers.len(), 2);
+    assert_eq!(headers[0].size(), 70000);
+    assert_eq!(headers[0].range(), 1024..1024 + 70000);
+    assert_eq!(headers[1].block(), 139);
+    assert_eq!(headers[1].mode().to_posix(), 0o644);
+    assert_eq!(headers[1].mtime().as_second(), 0x0A00_1234);
+
+    let s2 = std::fs::read("s2-bits").unwrap();
+    assert_eq!(Format::detect(&s2), Some(Format::Tap));
+}
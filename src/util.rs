@@ -1,6 +1,9 @@
-use std::fmt::{self, Write};
+use std::{
+    fmt::{self, Write},
+    ptr, slice,
+};
 
-macro_rules! int_ty(($T:ident, $Int:ty, $N:literal, |$b:ident| $get:expr) => {
+macro_rules! int_ty(($T:ident, $Int:ty, $N:literal, |$b:ident| $get:expr, |$v:ident| $from:expr) => {
     #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
     #[repr(transparent)]
     pub struct $T(pub [u8; $N]);
@@ -10,8 +13,15 @@ macro_rules! int_ty(($T:ident, $Int:ty, $N:literal, |$b:ident| $get:expr) => {
             let $b = self.0;
             $get
         }
+
+        pub fn set(&mut self, $v: $Int) {
+            self.0 = $from;
+        }
     }
 
+    // SAFETY: A byte array has alignment 1 and every bit pattern is valid.
+    unsafe impl View for $T {}
+
     impl fmt::Debug for $T {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             self.get().fmt(f)
@@ -26,7 +36,9 @@ macro_rules! int_ty(($T:ident, $Int:ty, $N:literal, |$b:ident| $get:expr) => {
 
     impl From<$Int> for $T {
         fn from(value: $Int) -> Self {
-            $T(value.to_le_bytes())
+            let mut v = $T([0; $N]);
+            v.set(value);
+            v
         }
     }
     impl From<$T> for $Int {
@@ -52,10 +64,121 @@ macro_rules! int_ty(($T:ident, $Int:ty, $N:literal, |$b:ident| $get:expr) => {
     }
 });
 
-int_ty!(U16Le, u16, 2, |b| u16::from_le_bytes(b));
-int_ty!(U32Me, u32, 4, |b| u32::from_le_bytes([
-    b[2], b[3], b[0], b[1]
-]));
+int_ty!(U16Le, u16, 2, |b| u16::from_le_bytes(b), |v| v
+    .to_le_bytes());
+int_ty!(
+    U32Me,
+    u32,
+    4,
+    |b| u32::from_le_bytes([b[2], b[3], b[0], b[1]]),
+    |v| {
+        let b = v.to_le_bytes();
+        [b[2], b[3], b[0], b[1]]
+    }
+);
+
+/// A type which can be viewed as bytes and from bytes.
+///
+/// This allows headers to be borrowed directly from untrusted tape data without
+/// copying. Implement it with [`impl_view!`], which checks the requirements.
+///
+/// # Safety
+///
+/// The type must have alignment 1, must have no padding, and must be valid for
+/// every bit pattern.
+pub unsafe trait View: Sized {
+    #[doc(hidden)]
+    const CHECK: () = assert!(align_of::<Self>() == 1);
+
+    /// Borrows a value from bytes of exactly its size.
+    fn ref_from_bytes(bytes: &[u8]) -> Option<&Self> {
+        let () = Self::CHECK;
+        if bytes.len() != size_of::<Self>() {
+            return None;
+        }
+        // SAFETY: The length is checked, the alignment is 1, and any bytes are
+        // valid.
+        Some(unsafe { &*bytes.as_ptr().cast::<Self>() })
+    }
+
+    /// Mutably borrows a value from bytes of exactly its size.
+    fn mut_from_bytes(bytes: &mut [u8]) -> Option<&mut Self> {
+        let () = Self::CHECK;
+        if bytes.len() != size_of::<Self>() {
+            return None;
+        }
+        // SAFETY: As for `ref_from_bytes`, and every value is valid as bytes.
+        Some(unsafe { &mut *bytes.as_mut_ptr().cast::<Self>() })
+    }
+
+    /// Borrows a value from the start of bytes and returns the rest.
+    fn ref_from_prefix(bytes: &[u8]) -> Option<(&Self, &[u8])> {
+        let (prefix, rest) = bytes.split_at_checked(size_of::<Self>())?;
+        Some((Self::ref_from_bytes(prefix)?, rest))
+    }
+
+    /// Copies a value from bytes of exactly its size.
+    fn read_from_bytes(bytes: &[u8]) -> Option<Self> {
+        // SAFETY: The reference is valid for reads.
+        Self::ref_from_bytes(bytes).map(|v| unsafe { ptr::read(v) })
+    }
+
+    /// The bytes of this value.
+    fn as_bytes(&self) -> &[u8] {
+        let () = Self::CHECK;
+        // SAFETY: There is no padding, so every byte is initialized.
+        unsafe { slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+
+    /// The bytes of this value, mutably.
+    fn as_mut_bytes(&mut self) -> &mut [u8] {
+        let () = Self::CHECK;
+        // SAFETY: As for `as_bytes`, and any bytes written are valid.
+        unsafe { slice::from_raw_parts_mut((self as *mut Self).cast(), size_of::<Self>()) }
+    }
+
+    /// Writes the bytes of this value to the start of `out`, returning `None`
+    /// if it is too short.
+    fn write_to_prefix(&self, out: &mut [u8]) -> Option<()> {
+        out.get_mut(..size_of::<Self>())?
+            .copy_from_slice(self.as_bytes());
+        Some(())
+    }
+}
+
+// SAFETY: `u8` has alignment 1 and every bit pattern is valid.
+unsafe impl View for u8 {}
+// SAFETY: An array has the alignment of its element and no padding between
+// elements.
+unsafe impl<T: View, const N: usize> View for [T; N] {}
+
+/// Implements [`View`] for a `#[repr(C)]` struct, checking at compile time that
+/// the listed fields are all `View` and that the struct has no padding.
+macro_rules! impl_view(($T:ty { $($field:ident: $F:ty),* $(,)? }) => {
+    // SAFETY: Every field is `View`, so has alignment 1 and is valid for every
+    // bit pattern, and the fields fill the struct, so there is no padding.
+    unsafe impl $crate::util::View for $T {}
+    const _: () = {
+        const fn assert_view<V: $crate::util::View>() {}
+        $(assert_view::<$F>();)*
+        assert!(size_of::<$T>() == 0 $(+ size_of::<$F>())*);
+        #[allow(dead_code)]
+        fn check_fields(v: &$T) {
+            $(let _: &$F = &v.$field;)*
+        }
+    };
+});
+pub(crate) use impl_view;
+
+/// Sums the little-endian words of the data, as for the checksums of tap and tp
+/// headers.
+pub fn sum_words(data: &[u8]) -> u16 {
+    let mut sum = 0u16;
+    for x in data.chunks_exact(2) {
+        sum = sum.wrapping_add(u16::from_le_bytes(x.try_into().unwrap()));
+    }
+    sum
+}
 
 pub struct Bytes<'a>(pub &'a [u8]);
 
@@ -83,7 +206,7 @@ pub struct BlockLen(pub usize);
 impl fmt::Debug for BlockLen {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let len = self.0;
-        if len % 512 == 0 && len > 512 {
+        if len.is_multiple_of(512) && len > 512 {
             write!(f, "{len} ({} * 512)", len / 512)
         } else {
             write!(f, "{len}")
@@ -96,3 +219,19 @@ impl fmt::Display for BlockLen {
         fmt::Debug::fmt(self, f)
     }
 }
+
+#[test]
+fn view_bounds() {
+    let bytes = [1, 2, 3, 4, 5];
+    assert_eq!(U16Le::ref_from_bytes(&bytes), None);
+    assert_eq!(U16Le::ref_from_bytes(&bytes[1..3]), Some(&U16Le([2, 3])));
+    let (v, rest) = U32Me::ref_from_prefix(&bytes).unwrap();
+    assert_eq!(v.get(), 0x0201_0403);
+    assert_eq!(rest, [5]);
+    assert_eq!(<[U16Le; 3]>::ref_from_prefix(&bytes), None);
+
+    let mut bytes = [0; 4];
+    U32Me::mut_from_bytes(&mut bytes).unwrap().set(0x0102_0304);
+    assert_eq!(bytes, [2, 1, 4, 3]);
+    assert_eq!(U32Me(bytes).write_to_prefix(&mut [0; 3]), None);
+}